@@ -1,4 +1,4 @@
-use crate::HeatmapOpts;
+use crate::parse_cmd::HeatmapOpts;
 use crate::stats::*;
 use crate::parse_cmd::*;
 use average::Mean;
@@ -6,6 +6,8 @@ use indicatif::*;
 use std::convert::*;
 use rayon::prelude::*;
 
+/// Compares each curve with every curve of the same or another energy bin,
+/// according to the [Mode]
 pub fn compare_curves(mut data: Data, p_bar: bool, cutoff: usize, mode: Mode) -> Stats
 {
     let mut diff_helper = Vec::new();
@@ -119,6 +121,7 @@ fn mode_cbrt(a: f64, b: f64) -> f64 {
     mode_abs(a,b).cbrt()
 }
 
+/// Same as [compare_curves], but uses `num_threds` threads
 pub fn compare_curves_parallel(mut data: Data, num_threds: usize, p_bar: bool, cutoff: usize, mode: Mode) -> Stats
 {
     let mut stats = Stats::new(data.data());
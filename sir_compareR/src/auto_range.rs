@@ -0,0 +1,95 @@
+//! Ranges of histogram axes that are determined from the data in a pre-pass,
+//! e.g. `'f 100 auto'` or `'u 100 auto 0.01'`
+use std::fmt;
+
+/// Range of a histogram axis that is determined from the data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoRange{
+    /// number of bins
+    pub bins: usize,
+    /// fraction of the samples that is clipped at each end,
+    /// i.e., the range goes from the `clip` to the `1 - clip` quantile
+    pub clip: f64
+}
+
+impl AutoRange{
+    /// Parses the arguments after `auto`, i.e., nothing or the clip quantile
+    pub fn parse<'a, I>(bins: usize, mut args: I) -> Result<Self, &'static str>
+    where I: Iterator<Item=&'a str>
+    {
+        let clip = match args.next() {
+            None => 0.0,
+            Some(clip) => clip.parse::<f64>()
+                .map_err(|_| "auto: unable to parse clip quantile")?
+        };
+        if args.next().is_some() {
+            return Err("auto: too many arguments. Usage: 'f bins auto [clip]'");
+        }
+        if !(0.0..0.5).contains(&clip) {
+            return Err("auto: clip quantile has to be in [0, 0.5)");
+        }
+        Ok(
+            Self{
+                bins,
+                clip
+            }
+        )
+    }
+
+    /// Smallest and largest value after clipping. NaN is ignored.
+    /// Error, if there are no values
+    pub fn bounds(&self, mut values: Vec<f64>) -> Result<(f64, f64), String>
+    {
+        values.retain(|val| !val.is_nan());
+        if values.is_empty() {
+            return Err("auto range - no values found".to_owned());
+        }
+        values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let last = values.len() - 1;
+        let lower = (self.clip * last as f64).floor() as usize;
+        let upper = ((1.0 - self.clip) * last as f64).ceil() as usize;
+        Ok((values[lower], values[upper]))
+    }
+
+    /// Range `[left, right)` of a float histogram containing all values within the bounds
+    pub fn f64_range(&self, values: Vec<f64>) -> Result<(f64, f64), String>
+    {
+        let (lower, upper) = self.bounds(values)?;
+        let width = upper - lower;
+        let range = if width > 0.0 {
+            // right border is exclusive
+            (lower, upper + width * 1e-9)
+        } else {
+            (lower - 0.5, upper + 0.5)
+        };
+        Ok(range)
+    }
+
+    /// Range `[left, right)` of an integer histogram containing all values within the bounds.
+    /// `right - left` is a multiple of the number of bins
+    pub fn int_range(&self, values: Vec<f64>) -> Result<(i64, i64), String>
+    {
+        let (lower, upper) = self.bounds(values)?;
+        let left = lower.floor() as i64;
+        let len = (upper.floor() as i64 - left + 1) as usize;
+        let bins = self.bins.max(1);
+        let len = len.div_ceil(bins) * bins;
+        Ok((left, left + len as i64))
+    }
+
+    /// Prints the chosen range
+    pub fn report<D: fmt::Display>(&self, axis: &str, left: D, right: D)
+    {
+        println!("auto range {}: left {} right {} ({})", axis, left, right, self);
+    }
+}
+
+impl fmt::Display for AutoRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.clip > 0.0 {
+            write!(f, "auto clip {}", self.clip)
+        } else {
+            write!(f, "auto")
+        }
+    }
+}
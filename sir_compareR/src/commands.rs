@@ -0,0 +1,568 @@
+use crate::parse_cmd::*;
+use crate::analyse::*;
+use crate::histogram::*;
+use crate::heatmap_generic::*;
+use crate::{stats, parse_files, histogram, heatmap2, hist_analyser, inspect, job, multi};
+use crate::heatmap2::{EitherH, CountMatrix, ValueAxis, PlotArgs};
+use crate::hist_analyser::{PercentResult, PercentBootstrap, HistSampler, HistDigest};
+use crate::header::HeaderParams;
+use crate::export::{Export, Array, ArrayData};
+use std::path::PathBuf;
+use std::{convert::TryInto, io::*};
+use std::result::Result;
+use std::fs::*;
+use std::process::Command;
+use std::sync::atomic::*;
+use sampling::*;
+use either::*;
+use colored::*;
+
+/// Executes the analysis and returns the names of the created files.
+/// Error, if the options are invalid
+pub fn run(options: Opt) -> Result<Vec<String>, String>
+{
+    match options {
+        Opt::Heatmap{..} => write_heatmap(options.try_into()?),
+        Opt::Histogram{..} => write_histogram(options.try_into()?),
+        Opt::Heatmap2{..} => write_heatmap2(options.try_into()?),
+        Opt::Percent{..} => write_percent(options.try_into()?),
+        Opt::GenericHeatmap{..} => generate_heatmap(options.try_into()?),
+        Opt::GenericHistogram{..} => generate_histogram(options.try_into()?),
+        Opt::Histogram3d{..} => generate_histogram_3d(options.try_into()?),
+        Opt::Inspect{..} => write_inspect(options.try_into()?),
+        Opt::Multi{..} => multi::write_multi(options.try_into()?),
+        Opt::Job{..} => job::run_jobs(options.try_into()?)
+    }
+}
+
+/// Resets the global counters and warning flags, 
+/// such that consecutive analyses, e.g. of a job file, do not report counts of each other
+pub fn reset_counters()
+{
+    parse_files::UNFINISHED_ENCOUNTERED.store(false, Ordering::Relaxed);
+    parse_files::UNFINISHED_COUNTER.store(0, Ordering::Relaxed);
+    heatmap2::WARNING_PRINTED.store(false, Ordering::Relaxed);
+    heatmap2::INVALID_LINE_PRINTED.store(false, Ordering::Relaxed);
+    heatmap2::FILTERED_COUNTER.store(0, Ordering::Relaxed);
+    heatmap2::MISS_COUNTER.store(0, Ordering::Relaxed);
+    crate::heatmap_generic::UNDEFINED_COUNTER.store(0, Ordering::Relaxed);
+    crate::heatmap_generic::FILTERED_COUNTER.store(0, Ordering::Relaxed);
+    crate::energy::OUT_OF_RANGE_COUNTER.store(0, Ordering::Relaxed);
+}
+
+/// Repeats the warning about normed integer curves at the end of an analysis, if it was printed
+pub fn warn_normed()
+{
+    if heatmap2::WARNING_PRINTED.load(Ordering::Relaxed)
+    {
+        eprintln!("{}", "Are you sure you wanted to norm the curves? This is probably a mistake!".red().bold())
+    }
+}
+
+/// Creates the output file `name`
+pub(crate) fn create_file(name: &str) -> Result<BufWriter<File>, String>
+{
+    File::create(name)
+        .map(BufWriter::new)
+        .map_err(|error| format!("unable to create {}: {}", name, error))
+}
+
+/// Compares all curves of all energy bins with each other, see [compare_curves]
+pub fn write_heatmap(opts: HeatmapOpts) -> Result<Vec<String>, String>
+{
+
+    let mut sorted_data = parse_files::parse_and_group_all_files(opts.clone());
+    if crate::parse_files::UNFINISHED_ENCOUNTERED.load(Ordering::Relaxed)
+    {
+        println!("UNFINISHED COUNT: {}", crate::parse_files::UNFINISHED_COUNTER.load(Ordering::Relaxed));
+    }
+    println!("average bin entries: {}", sorted_data.average_entries());
+    let num = if sorted_data.data().len() < 5 {
+        sorted_data.data().len()
+    } else {
+        5
+    };
+    println!("max {} entries: {:?}", num, sorted_data.max_n_entries(num));
+    println!("min {} entries: {:?}", num, sorted_data.min_n_entries(num));
+    if opts.print_bin_lens 
+    {
+        sorted_data.print_lens();
+    }
+    if let Some(maximum) = opts.max_entries {
+        sorted_data.limit_entries(maximum);
+        println!("new max {} entries: {:?}", num, sorted_data.max_n_entries(num));
+        println!("new average bin entries: {}", sorted_data.average_entries());
+        if opts.print_bin_lens 
+        {
+            println!("After limiting:");
+            sorted_data.print_lens();
+        }
+    }
+
+    // j == 0 is rejected by the option parsing
+    let matr =
+    match opts.j  {
+        1 => compare_curves(sorted_data, !opts.no_p_bar, opts.cutoff, opts.mode),
+        _ => compare_curves_parallel(sorted_data, opts.j, !opts.no_p_bar, opts.cutoff, opts.mode)
+         
+    };
+    let mut outputs = opts.stats_filenames().to_vec();
+    let mut export = Export::new(opts.generate_filename("stats"), &opts.export);
+    if !export.is_empty() {
+        let len = matr.get_curve_count().len();
+        let mean: Vec<f64> = matr.get_mean().iter().flatten().copied().collect();
+        let iterations: Vec<u64> = matr.get_iteration_count()
+            .iter()
+            .flatten()
+            .map(|&count| count as u64)
+            .collect();
+        let curve_count: Vec<u64> = matr.get_curve_count()
+            .iter()
+            .map(|&count| count as u64)
+            .collect();
+        export.array("mean", &["energy", "energy"], Array::new(vec![len, len], mean))
+            .array("iterations", &["energy", "energy"], Array::new(vec![len, len], iterations))
+            .array("curve_count", &["energy"], Array::vector(curve_count))
+            .array("energy_edges", &["edge"], Array::vector(opts.energy.edges()))
+            .info("mode", format!("{:?}", opts.mode))
+            .info("normed_curves", opts.norm);
+    }
+    outputs.extend(export.write(&opts.params));
+    write_matr(matr, opts);
+    Ok(outputs)
+}
+
+/// Writes the bootstrapped mean of the [HistReduce] values for each energy bin
+pub fn write_histogram(opts: HistogramOpts) -> Result<Vec<String>, String>
+{
+    let hist_data = histogram::parse_and_group_all_files(opts.clone());
+    let mut outputs = Vec::new();
+    if opts.bin_stats {
+        let stats = bin_stats(&hist_data, &opts.quantiles, opts.j);
+        let filename = opts.generate_filename("stats.dat");
+        write_bin_stats_table(&filename, stats, &opts.quantiles, &opts.params)?;
+        outputs.push(filename);
+    }
+    if opts.dump_samples {
+        let filename = opts.generate_filename("samples.dat");
+        write_samples(&filename, &hist_data, &opts.params)?;
+        outputs.push(filename);
+    }
+    let hist = histogramm_parallel(hist_data, opts.j, !opts.no_p_bar, opts.error);
+    
+    let filename = opts.generate_filename(".dat");
+    write_histogram_table(&filename, hist, &opts.error, &opts.params)?;
+    outputs.push(filename);
+    Ok(outputs)
+}
+
+fn write_bin_stats_table(filename: &str, stats: Vec<BinStats>, quantiles: &[f64], params: &HeaderParams) -> Result<(), String>
+{
+    let mut writer = create_file(filename)?;
+
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    params.write_header(&mut writer).unwrap();
+    write!(writer, "#index count mean std_dev min max").unwrap();
+    for q in quantiles
+    {
+        write!(writer, " q{}", q).unwrap();
+    }
+    writeln!(writer).unwrap();
+    for (index, stat) in stats.into_iter().enumerate()
+    {
+        write!(
+            writer,
+            "{} {} {:e} {:e} {:e} {:e}",
+            index,
+            stat.count,
+            stat.mean,
+            stat.std_dev,
+            stat.min,
+            stat.max
+        ).unwrap();
+        for q in stat.quantiles
+        {
+            write!(writer, " {:e}", q).unwrap();
+        }
+        writeln!(writer).unwrap();
+    }
+    Ok(())
+}
+
+/// one line per value: bin index and value
+fn write_samples(filename: &str, hist_data: &histogram::Histogram, params: &HeaderParams) -> Result<(), String>
+{
+    let mut writer = create_file(filename)?;
+
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    params.write_header(&mut writer).unwrap();
+    writeln!(writer, "#index value").unwrap();
+    for (index, bin) in hist_data.hist().iter().enumerate()
+    {
+        for val in bin
+        {
+            writeln!(writer, "{} {:e}", index, val).unwrap();
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn write_histogram_table(filename: &str, hist: Vec<BinEstimate>, error: &ErrorConfig, params: &HeaderParams) -> Result<(), String>
+{
+    let mut writer = create_file(filename)?;
+
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    params.write_header(&mut writer).unwrap();
+    error.write_header(&mut writer).unwrap();
+    for (index, estimate) in hist.into_iter().enumerate()
+    {
+        if error.interval {
+            writeln!(
+                writer,
+                "{} {:e} {:e} {:e} {:e}",
+                index,
+                estimate.mean,
+                estimate.error,
+                estimate.lower,
+                estimate.upper
+            ).unwrap();
+        } else {
+            writeln!(writer, "{} {:e} {:e}", index, estimate.mean, estimate.error).unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Writes a gnuplot script containing the heatmap of energy vs. [FunctionChooser](crate::heatmap2::FunctionChooser)
+pub fn write_heatmap2(mut opts: Heatmap2Opts) -> Result<Vec<String>, String>
+{
+    if opts.heatmap_builder.is_auto() {
+        let values = heatmap2::collect_feature_values(&opts.files, &opts.feature());
+        opts.heatmap_builder = opts.heatmap_builder.resolve(values)?;
+    }
+    let filename = opts.generate_filename("h2.gp");
+    println!("creating: {}", &filename);
+
+    let empty = opts.heatmap_builder.build(&opts.energy);
+    let (files, heatmap) = heatmap2::parse_and_count_all_files(&opts.files, &opts.feature(), empty);
+
+    let mut export = Export::new(opts.generate_filename("h2"), &opts.export);
+    export.info("feature", &opts.fun)
+        .info("heatmap", &opts.heatmap_builder);
+    let mut comments = file_comments(&files);
+    if let Some(filter) = opts.filter.as_ref() {
+        let filtered = heatmap2::FILTERED_COUNTER.swap(0, Ordering::Relaxed);
+        println!("FILTERED: {}", filtered);
+        comments.push(format!("where: {} filtered: {}", filter, filtered));
+        export.info("where", filter)
+            .info("filtered", filtered);
+    }
+    write_heatmap2_gnuplot(
+        &filename,
+        &comments,
+        heatmap,
+        opts.fun.label(),
+        opts.plot,
+        &opts.params,
+        &mut export
+    )?;
+    if opts.gnuplot_exec {
+        exec_gnuplot(&filename);
+    }
+    let mut outputs = vec![filename];
+    outputs.extend(export.write(&opts.params));
+    Ok(outputs)
+}
+
+pub(crate) fn exec_gnuplot(filename: &str)
+{
+    match Command::new("gnuplot")
+        .arg(filename)
+        .output()
+    {
+        Ok(_) => {},
+        Err(error) => {
+            eprintln!("{}", error)
+        }
+    }
+}
+
+/// Canonical paths of the files, used as comments in the output header
+pub(crate) fn file_comments(files: &[PathBuf]) -> Vec<String>
+{
+    files.iter()
+        .map(|p| canonicalize(p).unwrap().display().to_string())
+        .collect()
+}
+
+/// `comments` are written to the header, one per line
+pub(crate) fn write_heatmap2_gnuplot(
+    filename: &str,
+    comments: &[String],
+    heatmap: EitherH,
+    y_lab: String,
+    plot: PlotArgs,
+    params: &HeaderParams,
+    export: &mut Export
+) -> Result<(), String>
+{
+    let mut writer = create_file(filename)?;
+
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    params.write_header(&mut writer).unwrap();
+    for comment in comments {
+        writeln!(writer, "#{}", comment).unwrap();
+    }
+
+    let (total, misses) = match &heatmap {
+        Left(heat) => (heat.total(), heat.total_misses()),
+        Right(heat) => (heat.total(), heat.total_misses())
+    };
+    print_heatmap_totals(total, misses);
+
+    if !export.is_empty() {
+        let norm = plot.normalization();
+        match &heatmap {
+            Left(heat) => {
+                heat.export(export, norm, "energy", "feature");
+                export_edges(export, heat.width_hist().borders(), heat.height_hist().borders().clone());
+            },
+            Right(heat) => {
+                heat.export(export, norm, "energy", "feature");
+                let feature_edges: Vec<u64> = heat.height_hist()
+                    .borders()
+                    .iter()
+                    .map(|&border| border as u64)
+                    .collect();
+                export_edges(export, heat.width_hist().borders(), feature_edges);
+            }
+        }
+    }
+
+    if plot.is_custom() {
+        match &heatmap {
+            Left(heat) => heatmap2::write_heatmap_gnuplot(
+                writer,
+                filename,
+                heat,
+                ValueAxis::from_usize_borders(heat.width_hist().borders()),
+                ValueAxis::from_f64_borders(heat.height_hist().borders()),
+                &y_lab,
+                plot
+            ),
+            Right(heat) => heatmap2::write_heatmap_gnuplot(
+                writer,
+                filename,
+                heat,
+                ValueAxis::from_usize_borders(heat.width_hist().borders()),
+                ValueAxis::from_usize_borders(heat.height_hist().borders()),
+                &y_lab,
+                plot
+            )
+        }.unwrap();
+        return Ok(());
+    }
+
+    let mut settings = GnuplotSettings::new();
+    let palette = if plot.rgb {
+        GnuplotPalette::PresetRGB
+    } else {
+        let mut palette = CubeHelixParameter::default();
+        palette.gamma(0.65);
+        palette.into()
+    };
+    settings.x_label("E")
+        .y_label(y_lab)
+        .x_axis(GnuplotAxis::new(0.0, 1.0, 5))
+        .palette(palette);
+
+    match heatmap {
+        Left(heat) => {
+            let heat = heat.into_heatmap_normalized_columns();
+
+            let min_val = *heat.height_hist().borders().first().unwrap();
+            let max_val = *heat.height_hist()
+                .borders()
+                .last()
+                .unwrap();
+            settings.y_axis(GnuplotAxis::new(min_val, max_val, 5));
+
+            heat.gnuplot(
+                writer,
+                filename,
+                settings
+            ).unwrap();
+        },
+        Right(heat) => {
+            let heat = heat.into_heatmap_normalized_columns();
+
+            let min_val = *heat.height_hist().borders().first().unwrap() as f64;
+            let max_val = *heat.height_hist()
+                .borders()
+                .last()
+                .unwrap() - 1;
+            let max_val = max_val as f64;
+
+            settings.y_axis(GnuplotAxis::new(min_val, max_val, 5));
+
+            heat.gnuplot(
+                writer,
+                filename,
+                settings
+            ).unwrap();
+        }
+    };
+    Ok(())
+}
+
+fn export_edges<D: Into<ArrayData>>(export: &mut Export, energy_borders: &[usize], feature_edges: D)
+{
+    let energy_edges: Vec<u64> = energy_borders.iter()
+        .map(|&border| border as u64)
+        .collect();
+    export.array("energy_edges", &["edge"], Array::vector(energy_edges))
+        .array("feature_edges", &["edge"], Array::vector(feature_edges));
+}
+
+fn print_heatmap_totals(total: usize, misses: usize)
+{
+    println!("TOTAL: {}", total);
+    println!("OUTSIDE: {}", misses);
+    let frac = misses as f64 / total as f64;
+    println!("FRAC: {}", frac);
+}
+
+/// Writes the percentile of the [FunctionChooser](crate::heatmap2::FunctionChooser) values for each energy bin
+pub fn write_percent(opts: PercentOpts) -> Result<Vec<String>, String>
+{
+    let energy_hist = opts.energy.hist();
+    let spec = opts.feature();
+    let res = match (opts.sketch, opts.bootstrap.as_ref()) {
+        (Some(compression), _) => {
+            let empty = HistDigest::new(energy_hist, compression);
+            hist_analyser::parse_and_count_all_files(&opts.files, &spec, empty)
+                .percentiles(&opts.percentiles)
+        },
+        (None, bootstrap) => {
+            let empty = HistSampler::new(energy_hist);
+            let mut hist_percent = hist_analyser::parse_and_count_all_files(&opts.files, &spec, empty);
+            match bootstrap {
+                Some(config) => hist_percent.percentiles_bootstrap(&opts.percentiles, config),
+                None => hist_percent.percentiles(&opts.percentiles)
+            }
+        }
+    };
+    let name = opts.generate_filename("percent");
+    write_percent_table(&name, res, &opts.percentiles, opts.bootstrap.as_ref(), &opts.params)?;
+    Ok(vec![name])
+}
+
+/// Writes one line per energy bin: the bin borders, one column per percentile and the number of values.
+/// With `bootstrap`, each percentile is followed by its error and confidence interval
+pub(crate) fn write_percent_table(
+    name: &str,
+    res: Vec<PercentResult>,
+    percentiles: &[f64],
+    bootstrap: Option<&PercentBootstrap>,
+    params: &HeaderParams
+) -> Result<(), String>
+{
+    let mut buf = create_file(name)?;
+
+    let names: Vec<_> = percentiles.iter()
+        .map(|p| format!("p{}", p))
+        .collect();
+    writeln!(buf, "#{}", stats::get_cmd_args()).unwrap();
+    writeln!(buf, "#percent: {}", names.join(" ")).unwrap();
+    params.write_header(&mut buf).unwrap();
+    if let Some(config) = bootstrap {
+        config.write_header(&mut buf).unwrap();
+    }
+    
+    for (i, p_name) in names.iter().enumerate()
+    {
+        let max = res.iter()
+            .filter(|v| !v.values[i].is_nan())
+            .max_by(|a, b| a.values[i].partial_cmp(&b.values[i]).unwrap());
+        if let Some(max) = max {
+            writeln!(
+                buf,
+                "#{} max_val: {} max_val_bin_left: {} max_val_bin_right: {} count: {}",
+                p_name,
+                max.values[i],
+                max.left,
+                max.right,
+                max.count
+            ).unwrap();
+        }
+    }
+
+    let max_width = bootstrap.and_then(|config| config.max_width);
+    let columns: Vec<_> = match bootstrap {
+        Some(_) => names.iter()
+            .map(|p_name| format!("{0} {0}_error {0}_lower {0}_upper", p_name))
+            .collect(),
+        None => names.clone()
+    };
+    write!(buf, "#left right {} count", columns.join(" ")).unwrap();
+    if max_width.is_some() {
+        write!(buf, " wide").unwrap();
+    }
+    writeln!(buf).unwrap();
+
+    let mut wide_bins = 0;
+    for item in res {
+        let values: Vec<_> = if bootstrap.is_some() {
+            item.values
+                .iter()
+                .zip(item.intervals.iter())
+                .map(|(val, interval)| format!("{} {} {} {}", val, interval.error, interval.lower, interval.upper))
+                .collect()
+        } else {
+            item.values
+                .iter()
+                .map(|val| val.to_string())
+                .collect()
+        };
+        write!(buf, "{} {} {} {}", item.left, item.right, values.join(" "), item.count).unwrap();
+        if let Some(max_width) = max_width {
+            let wide = item.wide_intervals(max_width);
+            if wide > 0 {
+                wide_bins += 1;
+            }
+            write!(buf, " {}", wide).unwrap();
+        }
+        writeln!(buf).unwrap();
+    }
+    println!("generated: {}", name);
+    if let Some(max_width) = max_width {
+        println!("bins with confidence interval wider than {}: {}", max_width, wide_bins);
+    }
+    // columns per percentile
+    let step = if bootstrap.is_some() { 4 } else { 1 };
+    println!("easy plotting:");
+    for column in (0..percentiles.len()).map(|i| 3 + i * step)
+    {
+        if bootstrap.is_some() {
+            println!("p \"{}\" u 1:{}:{}:{} w yerrorbars", name, column, column + 2, column + 3);
+        } else {
+            println!("p \"{}\" u 1:{}", name, column);
+        }
+    }
+    println!("p \"{}\" u 1:{}", name, 3 + percentiles.len() * step);
+    Ok(())
+}
+
+/// Prints a summary of the data files and writes it as json
+pub fn write_inspect(opts: InspectOpts) -> Result<Vec<String>, String>
+{
+    let report = inspect::inspect_all_files(&opts);
+
+    let stdout = stdout();
+    report.write_human(stdout.lock()).unwrap();
+
+    let writer = create_file(&opts.json)?;
+    serde_json::to_writer_pretty(writer, &report).unwrap();
+    println!("generated: {}", &opts.json);
+    Ok(vec![opts.json])
+}
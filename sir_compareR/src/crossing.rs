@@ -0,0 +1,144 @@
+//! Times at which a curve crosses a threshold,
+//! linearly interpolated between the time steps
+use std::fmt;
+use std::str::FromStr;
+
+/// Threshold a curve has to cross
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold{
+    /// fraction of the maximum of the curve
+    Relative(f64),
+    /// absolute value
+    Absolute(f64)
+}
+
+impl Threshold{
+    pub fn value(&self, max: f64) -> f64
+    {
+        match self {
+            Threshold::Relative(fraction) => fraction * max,
+            Threshold::Absolute(val) => *val
+        }
+    }
+}
+
+/// Which flank of the curve is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flank{
+    /// first time the curve reaches the threshold
+    Rising,
+    /// first time the curve falls to or below the threshold after its maximum
+    Falling
+}
+
+/// Threshold crossing of a curve, e.g. `cross_rise_0.5` or `cross_fall_abs_10`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crossing{
+    pub flank: Flank,
+    pub threshold: Threshold
+}
+
+/// index and value of the first maximum
+pub(crate) fn max_helper(values: &[f64]) -> (usize, f64)
+{
+    let mut index = 0;
+    let mut max = f64::NEG_INFINITY;
+    for (i, &val) in values.iter().enumerate()
+    {
+        if val > max {
+            max = val;
+            index = i;
+        }
+    }
+    (index, max)
+}
+
+/// time at which the line from (x, y_left) to (x + 1, y_right) reaches threshold
+fn interpolate(x: usize, y_left: f64, y_right: f64, threshold: f64) -> f64
+{
+    x as f64 + (threshold - y_left) / (y_right - y_left)
+}
+
+impl Crossing{
+    /// Interpolated crossing time, None if the curve does not cross the threshold.
+    /// Relative thresholds are only defined for curves with a positive maximum
+    pub fn time(&self, values: &[f64]) -> Option<f64>
+    {
+        if values.is_empty() {
+            return None;
+        }
+        let (index_max, max) = max_helper(values);
+        if matches!(self.threshold, Threshold::Relative(_)) && max <= 0.0 {
+            return None;
+        }
+        let threshold = self.threshold.value(max);
+        match self.flank {
+            Flank::Rising => {
+                if values[0] >= threshold {
+                    return Some(0.0);
+                }
+                let i = values.iter()
+                    .position(|&val| val >= threshold)?;
+                Some(interpolate(i - 1, values[i - 1], values[i], threshold))
+            },
+            Flank::Falling => {
+                if max <= threshold {
+                    return None;
+                }
+                let i = (index_max + 1..values.len())
+                    .find(|&i| values[i] <= threshold)?;
+                Some(interpolate(i - 1, values[i - 1], values[i], threshold))
+            }
+        }
+    }
+
+    /// First time step at which the threshold is crossed, i.e.,
+    /// the crossing time rounded up
+    pub fn index(&self, values: &[f64]) -> Option<usize>
+    {
+        self.time(values)
+            .map(|t| t.ceil() as usize)
+    }
+}
+
+impl FromStr for Crossing {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const USAGE: &str = "Usage: cross_rise_X, cross_fall_X (X relative to maximum), cross_rise_abs_X or cross_fall_abs_X";
+        let lower = s.to_lowercase();
+        let rest = lower.strip_prefix("cross_")
+            .ok_or(USAGE)?;
+        let (flank, rest) = if let Some(rest) = rest.strip_prefix("rise_") {
+            (Flank::Rising, rest)
+        } else if let Some(rest) = rest.strip_prefix("fall_") {
+            (Flank::Falling, rest)
+        } else {
+            return Err(USAGE);
+        };
+        let threshold = if let Some(val) = rest.strip_prefix("abs_") {
+            Threshold::Absolute(val.parse().map_err(|_| USAGE)?)
+        } else {
+            let val = rest.strip_prefix("rel_").unwrap_or(rest);
+            Threshold::Relative(val.parse().map_err(|_| USAGE)?)
+        };
+        Ok(
+            Self{
+                flank,
+                threshold
+            }
+        )
+    }
+}
+
+impl fmt::Display for Crossing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flank = match self.flank {
+            Flank::Rising => "rise",
+            Flank::Falling => "fall"
+        };
+        match self.threshold {
+            Threshold::Relative(val) => write!(f, "cross_{}_{}", flank, val),
+            Threshold::Absolute(val) => write!(f, "cross_{}_abs_{}", flank, val)
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use sampling::HistUsize;
+use std::sync::atomic::*;
+use structopt::StructOpt;
+
+/// number of curves that were skipped, because their energy is outside of the [EnergyAxis]
+pub static OUT_OF_RANGE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Definition of the energy axis, used by all subcommands that bin curves by energy.
+///
+/// By default the energies are 1, ..., n.
+/// With `c0` the energies are 0, ..., n, i.e., C=0 is allowed.
+/// With `no_subtract` the energies are 0, ..., n-1
+#[derive(Debug, Clone, Copy)]
+pub struct EnergyAxis{
+    /// number of nodes that are reachable
+    pub n: usize,
+    /// actual number of nodes, if it differs from `n`
+    pub n_real: Option<usize>,
+    /// smallest energy
+    pub left: usize,
+    /// number of energies
+    pub len: usize,
+    /// number of energy bins
+    pub bins: usize
+}
+
+impl EnergyAxis{
+    /// Error, if the number of energies does not divide by `bins`
+    pub fn new(n: usize, n_real: Option<usize>, bins: usize, c0: bool, no_subtract: bool) -> Result<Self, String>
+    {
+        let left = if c0 || no_subtract {
+            0
+        } else {
+            1
+        };
+        let len = if c0 {
+            n + 1
+        } else {
+            n
+        };
+        if bins == 0 || len % bins != 0 {
+            return Err(format!("{} energies do not divide into {} bins", len, bins));
+        }
+        Ok(
+            Self{
+                n,
+                n_real,
+                left,
+                len,
+                bins
+            }
+        )
+    }
+
+    /// number of energies per bin
+    pub fn bin_size(&self) -> usize
+    {
+        self.len / self.bins
+    }
+
+    /// `n_real` if given, `n` otherwise
+    pub fn n_actual(&self) -> usize
+    {
+        self.n_real.unwrap_or(self.n)
+    }
+
+    /// Index of the bin of `energy`.
+    /// Returns None and increments [OUT_OF_RANGE_COUNTER] if the energy is outside of the axis
+    pub fn index(&self, energy: usize) -> Option<usize>
+    {
+        if energy < self.left || energy - self.left >= self.len {
+            OUT_OF_RANGE_COUNTER.fetch_add(1, Ordering::Relaxed);
+            None
+        } else {
+            Some((energy - self.left) / self.bin_size())
+        }
+    }
+
+    /// Borders of the energy bins, the right border of each bin is exclusive
+    pub fn edges(&self) -> Vec<u64>
+    {
+        (0..=self.bins)
+            .map(|i| (self.left + i * self.bin_size()) as u64)
+            .collect()
+    }
+
+    /// Histogram with one bin per energy bin
+    pub fn hist(&self) -> HistUsize
+    {
+        HistUsize::new(self.left, self.left + self.len, self.bins)
+            .expect("failed to create energy hist")
+    }
+
+    /// Part of the generated file names, e.g. `N1000` or `N1001_Reach1000`.
+    /// An axis including C=0 is marked with `c0`
+    pub fn name(&self) -> String
+    {
+        let mut name = format!("N{}", self.n_actual());
+        if self.n_actual() != self.n {
+            name.push_str(&format!("_Reach{}", self.n));
+        }
+        if self.len != self.n {
+            name.push_str("_c0");
+        }
+        name
+    }
+
+    /// Prints a warning if curves were skipped
+    pub fn report_out_of_range()
+    {
+        let skipped = OUT_OF_RANGE_COUNTER.swap(0, Ordering::Relaxed);
+        if skipped > 0 {
+            eprintln!("WARNING: skipped {} curves with energy outside of the energy axis", skipped);
+        }
+    }
+}
+
+// Command line options defining the [EnergyAxis], shared by all subcommands.
+// Not a doc comment, otherwise structopt uses it as description of the subcommands
+#[derive(Debug, Clone, Copy, StructOpt)]
+pub struct EnergyArgs{
+    /// Do not subtract 1 from the energy value, i.e., energies are 0, ..., n-1
+    #[structopt(long)]
+    pub no_subtract: bool,
+
+    /// Use this option when C=0 is allowed, i.e., energies are 0, ..., n
+    #[structopt(long)]
+    pub c0: bool
+}
+
+impl EnergyArgs{
+    /// `n_real` is only used in the names of the output files, see [EnergyAxis::name]
+    pub fn axis(&self, n: usize, bins: usize, n_real: Option<usize>) -> Result<EnergyAxis, String>
+    {
+        EnergyAxis::new(n, n_real, bins, self.c0, self.no_subtract)
+    }
+}
@@ -0,0 +1,508 @@
+//! Machine readable output of matrices and axes as NumPy `.npy`/`.npz` or CSV,
+//! together with a JSON file containing the metadata
+use crate::header::HeaderParams;
+use crate::stats;
+use flate2::Crc;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::*;
+use std::str::FromStr;
+use std::convert::TryFrom;
+
+/// Output format of [Export]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat{
+    /// one `.npy` file per array
+    Npy,
+    /// all arrays in one `.npz` archive
+    Npz,
+    /// one `.csv` file per array, 2D arrays are written row by row
+    Csv
+}
+
+impl FromStr for ExportFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "npy" => Ok(ExportFormat::Npy),
+            "npz" => Ok(ExportFormat::Npz),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err("Unknown export format. Valid: npy, npz, csv")
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ExportFormat::Npy => "npy",
+            ExportFormat::Npz => "npz",
+            ExportFormat::Csv => "csv"
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Values of an [Array]
+#[derive(Debug, Clone)]
+pub enum ArrayData{
+    U64(Vec<u64>),
+    F64(Vec<f64>)
+}
+
+impl ArrayData{
+    fn len(&self) -> usize
+    {
+        match self {
+            ArrayData::U64(data) => data.len(),
+            ArrayData::F64(data) => data.len()
+        }
+    }
+
+    fn dtype(&self) -> &'static str
+    {
+        match self {
+            ArrayData::U64(_) => "<u8",
+            ArrayData::F64(_) => "<f8"
+        }
+    }
+
+    fn write_le<W: Write>(&self, mut writer: W) -> Result<()>
+    {
+        match self {
+            ArrayData::U64(data) => {
+                for val in data {
+                    writer.write_all(&val.to_le_bytes())?;
+                }
+            },
+            ArrayData::F64(data) => {
+                for val in data {
+                    writer.write_all(&val.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value_string(&self, index: usize) -> String
+    {
+        match self {
+            ArrayData::U64(data) => data[index].to_string(),
+            ArrayData::F64(data) => format!("{:e}", data[index])
+        }
+    }
+}
+
+impl From<Vec<u64>> for ArrayData {
+    fn from(data: Vec<u64>) -> Self {
+        ArrayData::U64(data)
+    }
+}
+
+impl From<Vec<f64>> for ArrayData {
+    fn from(data: Vec<f64>) -> Self {
+        ArrayData::F64(data)
+    }
+}
+
+/// C ordered (row major) array, i.e., the last index is the fastest
+#[derive(Debug, Clone)]
+pub struct Array{
+    shape: Vec<usize>,
+    data: ArrayData
+}
+
+impl Array{
+    /// Panics if the shape does not match the number of values
+    pub fn new<D: Into<ArrayData>>(shape: Vec<usize>, data: D) -> Self
+    {
+        let data = data.into();
+        assert_eq!(
+            shape.iter().product::<usize>(),
+            data.len(),
+            "Array: shape does not match number of values"
+        );
+        Self{
+            shape,
+            data
+        }
+    }
+
+    /// one dimensional array
+    pub fn vector<D: Into<ArrayData>>(data: D) -> Self
+    {
+        let data = data.into();
+        Self{
+            shape: vec![data.len()],
+            data
+        }
+    }
+
+    fn npy_header(&self) -> Vec<u8>
+    {
+        let shape = match self.shape.len() {
+            1 => format!("({},)", self.shape[0]),
+            _ => {
+                let dims: Vec<_> = self.shape.iter().map(|d| d.to_string()).collect();
+                format!("({})", dims.join(", "))
+            }
+        };
+        let mut dict = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            self.data.dtype(),
+            shape
+        );
+        // magic (6) + version (2) + header length (2) + dict + '\n' is padded to a multiple of 64
+        let unpadded = 10 + dict.len() + 1;
+        let padding = (64 - unpadded % 64) % 64;
+        dict.push_str(&" ".repeat(padding));
+        dict.push('\n');
+
+        let mut header = Vec::with_capacity(10 + dict.len());
+        header.extend_from_slice(b"\x93NUMPY\x01\x00");
+        header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+        header.extend_from_slice(dict.as_bytes());
+        header
+    }
+
+    /// Writes the array in the `.npy` format
+    pub fn write_npy<W: Write>(&self, mut writer: W) -> Result<()>
+    {
+        writer.write_all(&self.npy_header())?;
+        self.data.write_le(writer)
+    }
+
+    /// Writes 1D arrays one value per line and 2D arrays row by row.
+    /// Higher dimensional arrays are written with the last index in one line
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> Result<()>
+    {
+        let line_len = if self.shape.len() > 1 {
+            *self.shape.last().unwrap()
+        } else {
+            1
+        };
+        let len = self.data.len();
+        for start in (0..len).step_by(line_len.max(1))
+        {
+            let line: Vec<_> = (start..start + line_len)
+                .map(|index| self.data.value_string(index))
+                .collect();
+            writeln!(writer, "{}", line.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn npy_bytes(&self) -> Vec<u8>
+    {
+        let mut bytes = Vec::new();
+        self.write_npy(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// Entry of the metadata file
+#[derive(Debug, Clone, Serialize)]
+pub struct ArrayInfo{
+    pub name: String,
+    pub dtype: String,
+    pub shape: Vec<usize>,
+    /// meaning of each index
+    pub axes: Vec<String>
+}
+
+/// Content of the JSON metadata file
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportMetadata{
+    pub command: String,
+    pub params: BTreeMap<String, String>,
+    pub arrays: Vec<ArrayInfo>,
+    pub info: BTreeMap<String, String>,
+    pub files: Vec<String>
+}
+
+/// Collects named arrays and writes them in all requested formats.
+///
+/// Files are called `{base}.{array}.npy`, `{base}.npz`, `{base}.{array}.csv` and `{base}.json`
+#[derive(Debug, Clone)]
+pub struct Export{
+    base: String,
+    formats: Vec<ExportFormat>,
+    arrays: Vec<(ArrayInfo, Array)>,
+    info: BTreeMap<String, String>
+}
+
+impl Export{
+    pub fn new(base: String, formats: &[ExportFormat]) -> Self
+    {
+        Self{
+            base,
+            formats: formats.to_vec(),
+            arrays: Vec::new(),
+            info: BTreeMap::new()
+        }
+    }
+
+    /// true if no format was requested, i.e., nothing will be written
+    pub fn is_empty(&self) -> bool
+    {
+        self.formats.is_empty()
+    }
+
+    /// Adds an array, `axes` names the meaning of each index
+    pub fn array(&mut self, name: &str, axes: &[&str], array: Array) -> &mut Self
+    {
+        let info = ArrayInfo{
+            name: name.to_owned(),
+            dtype: array.data.dtype().to_owned(),
+            shape: array.shape.clone(),
+            axes: axes.iter().map(|&axis| axis.to_owned()).collect()
+        };
+        self.arrays.push((info, array));
+        self
+    }
+
+    /// Adds an entry to the `info` section of the metadata
+    pub fn info<D: fmt::Display>(&mut self, key: &str, value: D) -> &mut Self
+    {
+        self.info.insert(key.to_owned(), value.to_string());
+        self
+    }
+
+    /// Writes all files and returns their names
+    pub fn write(self, params: &HeaderParams) -> Vec<String>
+    {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut files = Vec::new();
+        for format in self.formats.iter()
+        {
+            match format {
+                ExportFormat::Npy => {
+                    for (info, array) in self.arrays.iter()
+                    {
+                        let name = format!("{}.{}.npy", self.base, info.name);
+                        array.write_npy(BufWriter::new(File::create(&name).unwrap())).unwrap();
+                        files.push(name);
+                    }
+                },
+                ExportFormat::Csv => {
+                    for (info, array) in self.arrays.iter()
+                    {
+                        let name = format!("{}.{}.csv", self.base, info.name);
+                        array.write_csv(BufWriter::new(File::create(&name).unwrap())).unwrap();
+                        files.push(name);
+                    }
+                },
+                ExportFormat::Npz => {
+                    let name = format!("{}.npz", self.base);
+                    let entries: Vec<_> = self.arrays
+                        .iter()
+                        .map(|(info, array)| (format!("{}.npy", info.name), array.npy_bytes()))
+                        .collect();
+                    write_zip(BufWriter::new(File::create(&name).unwrap()), &entries).unwrap();
+                    files.push(name);
+                }
+            }
+        }
+
+        let json_name = format!("{}.json", self.base);
+        files.push(json_name.clone());
+        let metadata = ExportMetadata{
+            command: stats::get_cmd_args(),
+            params: params.params().clone(),
+            arrays: self.arrays.into_iter().map(|(info, _)| info).collect(),
+            info: self.info,
+            files: files.clone()
+        };
+        let writer = BufWriter::new(File::create(&json_name).unwrap());
+        serde_json::to_writer_pretty(writer, &metadata).unwrap();
+        for name in files.iter()
+        {
+            println!("exported: {}", name);
+        }
+        files
+    }
+}
+
+/// Writes an uncompressed zip archive, as read by `numpy.load`
+fn write_zip<W: Write>(mut writer: W, entries: &[(String, Vec<u8>)]) -> Result<()>
+{
+    // 1980-01-01 00:00
+    const DOS_TIME: u16 = 0;
+    const DOS_DATE: u16 = (1 << 5) | 1;
+    let to_u32 = |len: usize| u32::try_from(len)
+        .expect("npz: arrays larger than 4 GB are not supported");
+
+    let mut central = Vec::new();
+    let mut offset = 0;
+    for (name, data) in entries
+    {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let crc = crc.sum();
+        let size = to_u32(data.len());
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        local.extend_from_slice(&0x04034b50_u32.to_le_bytes());
+        local.extend_from_slice(&20_u16.to_le_bytes()); // version needed
+        local.extend_from_slice(&0_u16.to_le_bytes()); // flags
+        local.extend_from_slice(&0_u16.to_le_bytes()); // stored, i.e., no compression
+        local.extend_from_slice(&DOS_TIME.to_le_bytes());
+        local.extend_from_slice(&DOS_DATE.to_le_bytes());
+        local.extend_from_slice(&crc.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&0_u16.to_le_bytes()); // extra field
+        local.extend_from_slice(name.as_bytes());
+        writer.write_all(&local)?;
+        writer.write_all(data)?;
+
+        central.extend_from_slice(&0x02014b50_u32.to_le_bytes());
+        central.extend_from_slice(&20_u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&local[4..30]);
+        central.extend_from_slice(&0_u16.to_le_bytes()); // comment
+        central.extend_from_slice(&0_u16.to_le_bytes()); // disk
+        central.extend_from_slice(&0_u16.to_le_bytes()); // internal attributes
+        central.extend_from_slice(&0_u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&to_u32(offset).to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        offset += local.len() + data.len();
+    }
+    writer.write_all(&central)?;
+
+    let count = entries.len() as u16;
+    writer.write_all(&0x06054b50_u32.to_le_bytes())?;
+    writer.write_all(&0_u16.to_le_bytes())?; // disk
+    writer.write_all(&0_u16.to_le_bytes())?; // disk of central directory
+    writer.write_all(&count.to_le_bytes())?;
+    writer.write_all(&count.to_le_bytes())?;
+    writer.write_all(&to_u32(central.len()).to_le_bytes())?;
+    writer.write_all(&to_u32(offset).to_le_bytes())?;
+    writer.write_all(&0_u16.to_le_bytes())?; // comment
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], pos: usize) -> usize
+    {
+        u16::from_le_bytes([bytes[pos], bytes[pos + 1]]) as usize
+    }
+
+    fn u32_at(bytes: &[u8], pos: usize) -> u32
+    {
+        u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+    }
+
+    // bitwise CRC-32 (IEEE), independent of flate2
+    fn crc32(data: &[u8]) -> u32
+    {
+        let mut crc = !0_u32;
+        for &byte in data
+        {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB88320 & mask);
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn crc_check_value()
+    {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        let mut crc = Crc::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.sum(), 0xCBF43926);
+    }
+
+    #[test]
+    fn npy_header_padding()
+    {
+        let arrays = [
+            Array::vector(vec![1_u64, 2, 3]),
+            Array::vector(Vec::<f64>::new()),
+            Array::new(vec![2, 3], vec![0.5_f64; 6]),
+            Array::new(vec![10, 100, 1000], vec![0_u64; 1_000_000]),
+        ];
+        for array in arrays.iter()
+        {
+            let bytes = array.npy_bytes();
+            assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+            let header_len = u16_at(&bytes, 8);
+            assert_eq!((10 + header_len) % 64, 0);
+            let dict = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+            assert!(dict.ends_with('\n'));
+            assert!(dict.starts_with(&format!("{{'descr': '{}', 'fortran_order': False, ", array.data.dtype())));
+            assert_eq!(bytes.len(), 10 + header_len + 8 * array.data.len());
+        }
+        let dict = |array: &Array| {
+            let bytes = array.npy_bytes();
+            String::from_utf8(bytes[10..10 + u16_at(&bytes, 8)].to_vec()).unwrap()
+        };
+        assert!(dict(&arrays[0]).contains("'shape': (3,), }"));
+        assert!(dict(&arrays[2]).contains("'shape': (2, 3), }"));
+
+        let bytes = arrays[2].npy_bytes();
+        let data = &bytes[10 + u16_at(&bytes, 8)..];
+        assert_eq!(&data[..8], &0.5_f64.to_le_bytes());
+    }
+
+    #[test]
+    fn zip_round_trip()
+    {
+        let entries = vec![
+            ("counts.npy".to_owned(), Array::vector(vec![1_u64, 2, 3]).npy_bytes()),
+            ("empty.npy".to_owned(), Vec::new()),
+            ("x_edges.npy".to_owned(), Array::vector(vec![0.1_f64; 100]).npy_bytes()),
+        ];
+        let mut zip = Vec::new();
+        write_zip(&mut zip, &entries).unwrap();
+
+        // end of central directory record
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(&zip, end), 0x06054b50);
+        assert_eq!(u16_at(&zip, end + 8), entries.len());
+        assert_eq!(u16_at(&zip, end + 10), entries.len());
+        let central_size = u32_at(&zip, end + 12) as usize;
+        let central_offset = u32_at(&zip, end + 16) as usize;
+        assert_eq!(central_offset + central_size, end);
+
+        let mut pos = central_offset;
+        for (name, data) in entries.iter()
+        {
+            assert_eq!(u32_at(&zip, pos), 0x02014b50);
+            let crc = u32_at(&zip, pos + 16);
+            assert_eq!(crc, crc32(data));
+            assert_eq!(u32_at(&zip, pos + 20) as usize, data.len());
+            assert_eq!(u32_at(&zip, pos + 24) as usize, data.len());
+            let name_len = u16_at(&zip, pos + 28);
+            let extra_len = u16_at(&zip, pos + 30);
+            let comment_len = u16_at(&zip, pos + 32);
+            let local = u32_at(&zip, pos + 42) as usize;
+            assert_eq!(&zip[pos + 46..pos + 46 + name_len], name.as_bytes());
+
+            // local file header the central directory points to
+            assert_eq!(u32_at(&zip, local), 0x04034b50);
+            assert_eq!(u16_at(&zip, local + 8), 0);
+            assert_eq!(u32_at(&zip, local + 14), crc);
+            let local_name_len = u16_at(&zip, local + 26);
+            let local_extra_len = u16_at(&zip, local + 28);
+            assert_eq!(&zip[local + 30..local + 30 + local_name_len], name.as_bytes());
+            let start = local + 30 + local_name_len + local_extra_len;
+            assert_eq!(&zip[start..start + data.len()], &data[..]);
+
+            pos += 46 + name_len + extra_len + comment_len;
+        }
+        assert_eq!(pos, end);
+    }
+}
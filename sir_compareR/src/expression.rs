@@ -0,0 +1,941 @@
+//! Expressions over curve features, e.g. `val_max / n` or `index_max - from(0.1)`,
+//! and over the columns of a data line, e.g. `c3 / c0` or `log10(infected)`.
+//!
+//! An expression is parsed once and then evaluated for each curve or line.
+//! Comparisons and boolean operators (`&&`, `||`, `!`) evaluate to 1 (true) or 0 (false),
+//! any value other than 0 and NaN is true.
+use std::fmt;
+use std::str::FromStr;
+use crate::crossing::*;
+
+/// Feature of a curve or constant, that can be used as variable in an [Expression]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Var{
+    ValMax,
+    ValMin,
+    IndexMax,
+    IndexMin,
+    /// last index with a value that is not zero
+    Last,
+    Sum,
+    /// number of values of the curve
+    Len,
+    Energy,
+    /// extinction index, only known in the Histogram subcommand and in `--where` filters, NaN otherwise
+    Extinction,
+    /// number of nodes, has to be bound with [Expression::bind_n]
+    N
+}
+
+impl Var{
+    fn from_name(name: &str) -> Option<Self>
+    {
+        let var = match name {
+            "val_max" | "valmax" => Var::ValMax,
+            "val_min" | "valmin" => Var::ValMin,
+            "index_max" | "indexmax" => Var::IndexMax,
+            "index_min" | "indexmin" => Var::IndexMin,
+            "last" | "last_index_not_zero" | "lastindexnotzero" => Var::Last,
+            "sum" => Var::Sum,
+            "len" => Var::Len,
+            "energy" | "e" => Var::Energy,
+            "extinction" | "duration" => Var::Extinction,
+            "n" => Var::N,
+            _ => return None
+        };
+        Some(var)
+    }
+
+    fn name(&self) -> &'static str
+    {
+        match self {
+            Var::ValMax => "val_max",
+            Var::ValMin => "val_min",
+            Var::IndexMax => "index_max",
+            Var::IndexMin => "index_min",
+            Var::Last => "last",
+            Var::Sum => "sum",
+            Var::Len => "len",
+            Var::Energy => "energy",
+            Var::Extinction => "extinction",
+            Var::N => "n"
+        }
+    }
+}
+
+/// Truth value of the result of an expression: everything except 0 and NaN is true
+pub fn is_true(val: f64) -> bool
+{
+    val != 0.0 && !val.is_nan()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op{
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    Equal,
+    NotEqual,
+    And,
+    Or
+}
+
+impl Op{
+    fn symbol(&self) -> &'static str
+    {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Less => "<",
+            Op::LessEq => "<=",
+            Op::Greater => ">",
+            Op::GreaterEq => ">=",
+            Op::Equal => "==",
+            Op::NotEqual => "!=",
+            Op::And => "&&",
+            Op::Or => "||"
+        }
+    }
+
+    fn apply(&self, a: f64, b: f64) -> f64
+    {
+        let truth = |b: bool| if b {1.0} else {0.0};
+        match self {
+            Op::Add => a + b,
+            Op::Sub => a - b,
+            Op::Mul => a * b,
+            Op::Div => a / b,
+            Op::Less => truth(a < b),
+            Op::LessEq => truth(a <= b),
+            Op::Greater => truth(a > b),
+            Op::GreaterEq => truth(a >= b),
+            Op::Equal => truth(a == b),
+            Op::NotEqual => truth(a != b),
+            Op::And => truth(is_true(a) && is_true(b)),
+            Op::Or => truth(is_true(a) || is_true(b))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Func{
+    Min,
+    Max,
+    Abs,
+    Sqrt,
+    Ln,
+    Log10,
+    Exp,
+    /// first index at which the curve reaches the given fraction of its maximum
+    From,
+    /// interpolated time at which the curve reaches the given fraction of its maximum
+    Rise,
+    /// interpolated time at which the curve falls to the given fraction of its maximum after the peak
+    Fall
+}
+
+impl Func{
+    fn from_name(name: &str) -> Option<Self>
+    {
+        let fun = match name {
+            "min" => Func::Min,
+            "max" => Func::Max,
+            "abs" => Func::Abs,
+            "sqrt" => Func::Sqrt,
+            "ln" => Func::Ln,
+            "log10" => Func::Log10,
+            "exp" => Func::Exp,
+            "from" => Func::From,
+            "rise" => Func::Rise,
+            "fall" => Func::Fall,
+            _ => return None
+        };
+        Some(fun)
+    }
+
+    fn name(&self) -> &'static str
+    {
+        match self {
+            Func::Min => "min",
+            Func::Max => "max",
+            Func::Abs => "abs",
+            Func::Sqrt => "sqrt",
+            Func::Ln => "ln",
+            Func::Log10 => "log10",
+            Func::Exp => "exp",
+            Func::From => "from",
+            Func::Rise => "rise",
+            Func::Fall => "fall"
+        }
+    }
+
+    /// functions that need the whole curve
+    fn is_curve_function(&self) -> bool
+    {
+        matches!(self, Func::From | Func::Rise | Func::Fall)
+    }
+
+    fn arg_count(&self) -> usize
+    {
+        match self {
+            Func::Min | Func::Max => 2,
+            _ => 1
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node{
+    Num(f64),
+    Var(Var),
+    /// column of a data line
+    Column(usize),
+    /// column of a data line given by name, see [ColumnExpression::resolve_names]
+    Name(String),
+    Neg(Box<Node>),
+    /// logical not, see [is_true]
+    Not(Box<Node>),
+    Bin(Op, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>)
+}
+
+/// The curve an [Expression] is evaluated for
+#[derive(Debug, Clone, Copy)]
+pub struct Curve<'a>{
+    pub values: &'a [f64],
+    pub energy: usize,
+    pub extinction_index: Option<usize>
+}
+
+impl<'a> Curve<'a>{
+    fn max(&self) -> (usize, f64)
+    {
+        let mut index = 0;
+        let mut max = f64::NEG_INFINITY;
+        for (i, &val) in self.values.iter().enumerate()
+        {
+            if val > max {
+                max = val;
+                index = i;
+            }
+        }
+        (index, max)
+    }
+
+    fn min(&self) -> (usize, f64)
+    {
+        let mut index = 0;
+        let mut min = f64::INFINITY;
+        for (i, &val) in self.values.iter().enumerate()
+        {
+            if val < min {
+                min = val;
+                index = i;
+            }
+        }
+        (index, min)
+    }
+
+    fn curve_var(&self, var: Var) -> f64
+    {
+        match var {
+            Var::ValMax => self.max().1,
+            Var::ValMin => self.min().1,
+            Var::IndexMax => self.max().0 as f64,
+            Var::IndexMin => self.min().0 as f64,
+            Var::Last => {
+                self.values
+                    .iter()
+                    .rposition(|&val| val != 0.0)
+                    .unwrap_or(0) as f64
+            },
+            Var::Sum => self.values.iter().sum(),
+            Var::Len => self.values.len() as f64,
+            Var::Energy => self.energy as f64,
+            Var::Extinction => {
+                match self.extinction_index {
+                    Some(index) if index != usize::MAX => index as f64,
+                    _ => f64::NAN
+                }
+            },
+            Var::N => panic!("n is not bound - this is a bug")
+        }
+    }
+
+    fn from(&self, fraction: f64) -> f64
+    {
+        let threshold = self.max().1 * fraction;
+        self.values
+            .iter()
+            .position(|&val| val >= threshold)
+            .map_or(f64::NAN, |index| index as f64)
+    }
+
+    fn crossing(&self, flank: Flank, fraction: f64) -> f64
+    {
+        let crossing = Crossing{
+            flank,
+            threshold: Threshold::Relative(fraction)
+        };
+        crossing.time(self.values)
+            .unwrap_or(f64::NAN)
+    }
+}
+
+/// Values an expression is evaluated with
+trait Scope{
+    fn var(&self, var: Var) -> f64;
+    fn column(&self, index: usize) -> f64;
+    /// functions that need the whole curve, see [Func::is_curve_function]
+    fn curve_function(&self, fun: Func, arg: f64) -> f64;
+}
+
+impl<'a> Scope for Curve<'a>{
+    fn var(&self, var: Var) -> f64
+    {
+        self.curve_var(var)
+    }
+
+    fn column(&self, _: usize) -> f64
+    {
+        unreachable!("columns are not parsed in curve expressions")
+    }
+
+    fn curve_function(&self, fun: Func, arg: f64) -> f64
+    {
+        match fun {
+            Func::From => self.from(arg),
+            Func::Rise => self.crossing(Flank::Rising, arg),
+            Func::Fall => self.crossing(Flank::Falling, arg),
+            _ => unreachable!()
+        }
+    }
+}
+
+/// Columns of a data line, a [ColumnExpression] is evaluated for
+struct Columns<'a>(&'a [f64]);
+
+impl<'a> Scope for Columns<'a>{
+    fn var(&self, _: Var) -> f64
+    {
+        unreachable!("curve features are not parsed in column expressions")
+    }
+
+    fn column(&self, index: usize) -> f64
+    {
+        self.0.get(index)
+            .copied()
+            .unwrap_or(f64::NAN)
+    }
+
+    fn curve_function(&self, _: Func, _: f64) -> f64
+    {
+        unreachable!("curve functions are not parsed in column expressions")
+    }
+}
+
+impl Node{
+    fn eval<S: Scope>(&self, scope: &S) -> f64
+    {
+        match self {
+            Node::Num(val) => *val,
+            Node::Var(var) => scope.var(*var),
+            Node::Column(index) => scope.column(*index),
+            Node::Name(name) => panic!("column {} is not resolved - this is a bug", name),
+            Node::Neg(node) => -node.eval(scope),
+            Node::Not(node) => if is_true(node.eval(scope)) {0.0} else {1.0},
+            Node::Bin(op, a, b) => op.apply(a.eval(scope), b.eval(scope)),
+            Node::Call(fun, args) => {
+                let a = args[0].eval(scope);
+                match fun {
+                    Func::Min => a.min(args[1].eval(scope)),
+                    Func::Max => a.max(args[1].eval(scope)),
+                    Func::Abs => a.abs(),
+                    Func::Sqrt => a.sqrt(),
+                    Func::Ln => a.ln(),
+                    Func::Log10 => a.log10(),
+                    Func::Exp => a.exp(),
+                    Func::From | Func::Rise | Func::Fall => scope.curve_function(*fun, a)
+                }
+            }
+        }
+    }
+
+    fn bind_n(&mut self, n: f64)
+    {
+        match self {
+            Node::Var(Var::N) => *self = Node::Num(n),
+            Node::Num(_) | Node::Var(_) | Node::Column(_) | Node::Name(_) => {},
+            Node::Neg(node) | Node::Not(node) => node.bind_n(n),
+            Node::Bin(_, a, b) => {
+                a.bind_n(n);
+                b.bind_n(n);
+            },
+            Node::Call(_, args) => {
+                args.iter_mut()
+                    .for_each(|arg| arg.bind_n(n))
+            }
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            Node::Num(val) => write!(f, "{}", val),
+            Node::Var(var) => write!(f, "{}", var.name()),
+            Node::Column(index) => write!(f, "c{}", index),
+            Node::Name(name) => write!(f, "{}", name),
+            Node::Neg(node) => {
+                write!(f, "(-")?;
+                node.write(f)?;
+                write!(f, ")")
+            },
+            Node::Not(node) => {
+                write!(f, "(!")?;
+                node.write(f)?;
+                write!(f, ")")
+            },
+            Node::Bin(op, a, b) => {
+                write!(f, "(")?;
+                a.write(f)?;
+                write!(f, " {} ", op.symbol())?;
+                b.write(f)?;
+                write!(f, ")")
+            },
+            Node::Call(fun, args) => {
+                write!(f, "{}(", fun.name())?;
+                for (i, arg) in args.iter().enumerate()
+                {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    arg.write(f)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token{
+    Num(f64),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Comma
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String>
+{
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            let is_digit = |i: usize| chars.get(i).is_some_and(|c| c.is_ascii_digit());
+            while is_digit(i) || chars.get(i) == Some(&'.') {
+                i += 1;
+            }
+            // exponent, e.g. 1e-3
+            if chars.get(i).is_some_and(|&c| c == 'e' || c == 'E') {
+                let sign = chars.get(i + 1).is_some_and(|&c| c == '-' || c == '+');
+                let digits_start = if sign {i + 2} else {i + 1};
+                if is_digit(digits_start) {
+                    i = digits_start;
+                    while is_digit(i) {
+                        i += 1;
+                    }
+                }
+            }
+            let num: String = chars[start..i].iter().collect();
+            let num = num.parse::<f64>()
+                .map_err(|_| format!("invalid number '{}'", num))?;
+            tokens.push(Token::Num(num));
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_')
+            {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            tokens.push(Token::Ident(ident.to_lowercase()));
+            continue;
+        }
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            ('<', Some('=')) => (Token::Op("<="), 2),
+            ('>', Some('=')) => (Token::Op(">="), 2),
+            ('=', Some('=')) => (Token::Op("=="), 2),
+            ('!', Some('=')) => (Token::Op("!="), 2),
+            ('&', Some('&')) => (Token::Op("&&"), 2),
+            ('|', Some('|')) => (Token::Op("||"), 2),
+            ('!', _) => (Token::Op("!"), 1),
+            ('<', _) => (Token::Op("<"), 1),
+            ('>', _) => (Token::Op(">"), 1),
+            ('+', _) => (Token::Op("+"), 1),
+            ('-', _) => (Token::Op("-"), 1),
+            ('*', _) => (Token::Op("*"), 1),
+            ('/', _) => (Token::Op("/"), 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            (',', _) => (Token::Comma, 1),
+            _ => return Err(format!("unexpected character '{}'", c))
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser{
+    tokens: Vec<Token>,
+    pos: usize,
+    /// identifiers are columns instead of curve features
+    columns: bool
+}
+
+impl Parser{
+    fn peek(&self) -> Option<&Token>
+    {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token>
+    {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String>
+    {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            Some(t) => Err(format!("expected {:?}, found {:?}", token, t)),
+            None => Err(format!("expected {:?}, found end of expression", token))
+        }
+    }
+
+    fn peek_op(&self, ops: &[&'static str]) -> Option<&'static str>
+    {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => Some(*op),
+            _ => None
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String>
+    {
+        let mut left = self.and()?;
+        while self.peek_op(&["||"]).is_some() {
+            self.pos += 1;
+            let right = self.and()?;
+            left = Node::Bin(Op::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Node, String>
+    {
+        let mut left = self.comparison()?;
+        while self.peek_op(&["&&"]).is_some() {
+            self.pos += 1;
+            let right = self.comparison()?;
+            left = Node::Bin(Op::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String>
+    {
+        let left = self.additive()?;
+        let op = match self.peek_op(&["<", "<=", ">", ">=", "==", "!="]) {
+            Some(op) => op,
+            None => return Ok(left)
+        };
+        self.pos += 1;
+        let right = self.additive()?;
+        let op = match op {
+            "<" => Op::Less,
+            "<=" => Op::LessEq,
+            ">" => Op::Greater,
+            ">=" => Op::GreaterEq,
+            "==" => Op::Equal,
+            _ => Op::NotEqual
+        };
+        Ok(Node::Bin(op, Box::new(left), Box::new(right)))
+    }
+
+    fn additive(&mut self) -> Result<Node, String>
+    {
+        let mut left = self.multiplicative()?;
+        while let Some(op) = self.peek_op(&["+", "-"]) {
+            self.pos += 1;
+            let right = self.multiplicative()?;
+            let op = if op == "+" {Op::Add} else {Op::Sub};
+            left = Node::Bin(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn multiplicative(&mut self) -> Result<Node, String>
+    {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek_op(&["*", "/"]) {
+            self.pos += 1;
+            let right = self.unary()?;
+            let op = if op == "*" {Op::Mul} else {Op::Div};
+            left = Node::Bin(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String>
+    {
+        if self.peek_op(&["-"]).is_some() {
+            self.pos += 1;
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        if self.peek_op(&["!"]).is_some() {
+            self.pos += 1;
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        if self.peek_op(&["+"]).is_some() {
+            self.pos += 1;
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String>
+    {
+        match self.next() {
+            Some(Token::Num(val)) => Ok(Node::Num(val)),
+            Some(Token::Open) => {
+                let node = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(node)
+            },
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::Open) {
+                    self.pos += 1;
+                    let fun = Func::from_name(&name)
+                        .ok_or_else(|| format!("unknown function '{}'", name))?;
+                    if self.columns && fun.is_curve_function() {
+                        return Err(format!("{} can only be used for curves", name));
+                    }
+                    let mut args = vec![self.or()?];
+                    while self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                        args.push(self.or()?);
+                    }
+                    self.expect(Token::Close)?;
+                    if args.len() != fun.arg_count() {
+                        return Err(
+                            format!("{} expects {} arguments, found {}", name, fun.arg_count(), args.len())
+                        );
+                    }
+                    Ok(Node::Call(fun, args))
+                } else if self.columns {
+                    let index = name.strip_prefix('c')
+                        .and_then(|index| index.parse::<usize>().ok());
+                    match index {
+                        Some(index) => Ok(Node::Column(index)),
+                        None => Ok(Node::Name(name))
+                    }
+                } else {
+                    Var::from_name(&name)
+                        .map(Node::Var)
+                        .ok_or_else(|| format!("unknown variable '{}'", name))
+                }
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_owned())
+        }
+    }
+}
+
+/// Parsed expression over the features of a curve.
+///
+/// Variables: val_max, val_min, index_max, index_min, last, sum, len, energy, extinction, n.
+/// Functions: min(a, b), max(a, b), abs(a), sqrt(a), ln(a), log10(a), exp(a) and
+/// from(x), the first index at which the curve reaches x times its maximum,
+/// rise(x) and fall(x), the interpolated times at which the curve crosses x times its maximum.
+/// Operators: + - * / < <= > >= == != && || !
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression{
+    root: Node
+}
+
+impl Expression{
+    /// Replaces the variable n by the number of nodes
+    pub fn bind_n(&mut self, n: usize)
+    {
+        self.root.bind_n(n as f64)
+    }
+
+    pub fn eval(&self, curve: &Curve) -> f64
+    {
+        self.root.eval(curve)
+    }
+
+    /// Name that can be used as part of a file name
+    pub fn file_name(&self) -> String
+    {
+        let mut name = String::from("expr_");
+        for c in self.to_string().chars()
+        {
+            match c {
+                ' ' => {},
+                '+' => name.push_str("plus"),
+                '-' => name.push_str("minus"),
+                '*' => name.push_str("mul"),
+                '/' => name.push_str("div"),
+                '<' => name.push_str("lt"),
+                '>' => name.push_str("gt"),
+                '=' => name.push_str("eq"),
+                '!' => name.push_str("not"),
+                '&' => name.push_str("and"),
+                '|' => name.push_str("or"),
+                '(' | ')' | ',' => name.push('_'),
+                _ => name.push(c)
+            }
+        }
+        name
+    }
+}
+
+fn parse(s: &str, columns: bool) -> Result<Node, String>
+{
+    let tokens = tokenize(s)?;
+    let mut parser = Parser{tokens, pos: 0, columns};
+    let root = parser.or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected {:?} in expression '{}'", token, s));
+    }
+    Ok(root)
+}
+
+impl FromStr for Expression {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s, false)
+            .map(|root| Self{root})
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.root.write(f)
+    }
+}
+
+/// Parsed expression over the columns of a data line.
+///
+/// Columns are `c0`, `c1`, ... or names from the header, see [ColumnExpression::resolve_names].
+/// A plain number, e.g. `3`, is the column with that index.
+/// Functions and operators are the same as for [Expression], except for from, rise and fall
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnExpression{
+    root: Node
+}
+
+impl ColumnExpression{
+    /// Value of the expression, NaN if a column is missing
+    pub fn eval(&self, columns: &[f64]) -> f64
+    {
+        self.root.eval(&Columns(columns))
+    }
+
+    /// Index of the column, if the expression is just a column
+    pub fn as_column(&self) -> Option<usize>
+    {
+        match self.root {
+            Node::Column(index) => Some(index),
+            _ => None
+        }
+    }
+
+    /// Largest column index used by the expression, None if no column is used
+    pub fn max_column(&self) -> Option<usize>
+    {
+        fn max(node: &Node) -> Option<usize>
+        {
+            match node {
+                Node::Column(index) => Some(*index),
+                Node::Neg(node) | Node::Not(node) => max(node),
+                Node::Bin(_, a, b) => max(a).max(max(b)),
+                Node::Call(_, args) => args.iter().filter_map(max).max(),
+                _ => None
+            }
+        }
+        max(&self.root)
+    }
+
+    /// true if the expression uses column names, that have to be resolved
+    pub fn has_names(&self) -> bool
+    {
+        fn names(node: &Node) -> bool
+        {
+            match node {
+                Node::Name(_) => true,
+                Node::Neg(node) | Node::Not(node) => names(node),
+                Node::Bin(_, a, b) => names(a) || names(b),
+                Node::Call(_, args) => args.iter().any(names),
+                _ => false
+            }
+        }
+        names(&self.root)
+    }
+
+    /// Replaces column names by their index in `names` (case insensitive)
+    pub fn resolve_names<S: AsRef<str>>(&mut self, names: &[S]) -> Result<(), String>
+    {
+        fn resolve<S: AsRef<str>>(node: &mut Node, names: &[S]) -> Result<(), String>
+        {
+            match node {
+                Node::Name(name) => {
+                    let index = names.iter()
+                        .position(|n| n.as_ref().eq_ignore_ascii_case(name))
+                        .ok_or_else(|| format!("column '{}' not found in header", name))?;
+                    *node = Node::Column(index);
+                    Ok(())
+                },
+                Node::Neg(node) | Node::Not(node) => resolve(node, names),
+                Node::Bin(_, a, b) => {
+                    resolve(a, names)?;
+                    resolve(b, names)
+                },
+                Node::Call(_, args) => {
+                    args.iter_mut()
+                        .try_for_each(|arg| resolve(arg, names))
+                },
+                _ => Ok(())
+            }
+        }
+        resolve(&mut self.root, names)
+    }
+}
+
+impl FromStr for ColumnExpression {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.trim().parse::<usize>() {
+            return Ok(Self{root: Node::Column(index)});
+        }
+        parse(s, true)
+            .map(|root| Self{root})
+    }
+}
+
+impl fmt::Display for ColumnExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.root.write(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [f64; 6] = [0.0, 2.0, 6.0, 4.0, 1.0, 0.0];
+
+    fn eval(s: &str) -> f64
+    {
+        let curve = Curve{
+            values: &VALUES,
+            energy: 3,
+            extinction_index: None
+        };
+        s.parse::<Expression>()
+            .unwrap()
+            .eval(&curve)
+    }
+
+    #[test]
+    fn precedence()
+    {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 - 4 - 2"), 2.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("-2 * 3"), -6.0);
+        assert_eq!(eval("1 + 2 < 4"), 1.0);
+        assert_eq!(eval("val_max / 4 + index_max"), 3.5);
+        assert_eq!(eval("max(1, 2) * min(3, 4)"), 6.0);
+    }
+
+    #[test]
+    fn not()
+    {
+        assert_eq!(eval("!0"), 1.0);
+        assert_eq!(eval("!2"), 0.0);
+        assert_eq!(eval("!!2"), 1.0);
+        assert_eq!(eval("!(energy > 5)"), 1.0);
+        // ! binds stronger than comparisons
+        assert_eq!(eval("!0 == 1"), 1.0);
+    }
+
+    #[test]
+    fn and_or()
+    {
+        assert_eq!(eval("1 && 2"), 1.0);
+        assert_eq!(eval("1 && 0"), 0.0);
+        assert_eq!(eval("0 || 3"), 1.0);
+        assert_eq!(eval("0 || 0"), 0.0);
+        // && binds stronger than ||
+        assert_eq!(eval("1 || 1 && 0"), 1.0);
+        assert_eq!(eval("(1 || 1) && 0"), 0.0);
+        // NaN is false
+        assert_eq!(eval("extinction || 0"), 0.0);
+        assert_eq!(eval("!extinction"), 1.0);
+        assert_eq!(eval("val_max > 5 && energy == 3"), 1.0);
+    }
+
+    #[test]
+    fn bound_n()
+    {
+        let mut expr: Expression = "val_max / n".parse().unwrap();
+        expr.bind_n(4);
+        let curve = Curve{values: &VALUES, energy: 0, extinction_index: None};
+        assert_eq!(expr.eval(&curve), 1.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "n is not bound")]
+    fn unbound_n()
+    {
+        eval("val_max / n");
+    }
+
+    #[test]
+    fn parse_errors()
+    {
+        let err = |s: &str| s.parse::<Expression>().unwrap_err();
+        assert!(err("foo + 1").contains("unknown variable 'foo'"));
+        assert!(err("foo(1)").contains("unknown function 'foo'"));
+        assert!(err("1 +").contains("unexpected end of expression"));
+        assert!(err("1 2").contains("unexpected"));
+        assert!(err("(1 + 2").contains("found end of expression"));
+        assert!(err("min(1)").contains("min expects 2 arguments, found 1"));
+        assert!(err("").contains("unexpected end of expression"));
+        assert!("c0 + 1".parse::<Expression>().is_err());
+        assert!("rise(0.5)".parse::<ColumnExpression>().is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::input::open_input;
+use std::io::*;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::result::Result;
+use std::fmt;
+
+/// Keys that are allowed to differ between files of one glob,
+/// since they are different for each run anyway
+const PER_FILE_KEYS: [&str; 10] = [
+    "seed", "time", "date", "host", "hostname", "pid", "threads", "save", "file", "commit"
+];
+
+/// Run parameters, as found in the `#` comment lines at the start of a data file.
+///
+/// Understands entries like `N: 3000`, `n=3000` as well as
+/// the Debug format of structs, e.g., `#Opts { n: 3000, seed: 2 }`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderParams{
+    params: BTreeMap<String, String>
+}
+
+fn is_key(s: &str) -> bool
+{
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        },
+        _ => false
+    }
+}
+
+fn is_per_file_key(key: &str) -> bool
+{
+    let lower = key.to_lowercase();
+    PER_FILE_KEYS.iter()
+        .any(|k| lower == *k || lower.ends_with(&format!("_{}", k)))
+}
+
+impl HeaderParams{
+    /// Parses the key value pairs of all given comment lines.
+    /// Keys are stored in lowercase.
+    /// If a key occurs multiple times, the first value is used
+    pub fn from_lines<I, S>(lines: I) -> Self
+    where I: IntoIterator<Item=S>,
+        S: AsRef<str>
+    {
+        let mut params = BTreeMap::new();
+        for line in lines
+        {
+            let line = line.as_ref()
+                .trim()
+                .trim_start_matches('#');
+            let segments = line.split(
+                [',', '{', '}', '(', ')', ';', '[', ']']
+            );
+            for segment in segments
+            {
+                let pos = match segment.find([':', '=']) {
+                    Some(pos) => pos,
+                    None => continue
+                };
+                let key = segment[..pos].split_whitespace().last();
+                let value = segment[pos+1..].trim().trim_matches('"');
+                if let Some(key) = key {
+                    if is_key(key) && !value.is_empty() {
+                        params.entry(key.to_lowercase())
+                            .or_insert_with(|| value.to_owned());
+                    }
+                }
+            }
+        }
+        Self{params}
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str>
+    {
+        self.params
+            .get(key)
+            .map(|v| v.as_str())
+    }
+
+    pub fn params(&self) -> &BTreeMap<String, String>
+    {
+        &self.params
+    }
+
+    /// Number of nodes, i.e., the value of the key `n` or `N`
+    pub fn n(&self) -> Option<usize>
+    {
+        self.get("n")
+            .and_then(|val| val.parse::<usize>().ok())
+    }
+
+    /// Write all parameters as comment lines
+    pub fn write_header<W: Write>(&self, mut writer: W) -> std::io::Result<()>
+    {
+        for (key, val) in self.params.iter()
+        {
+            writeln!(writer, "#param {}: {}", key, val)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns all comment lines before the first data line
+pub fn read_header_lines<R: Read>(reader: R) -> Vec<String>
+{
+    let buf_reader = BufReader::new(reader);
+    let mut header = Vec::new();
+    for line in buf_reader.lines()
+    {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !trimmed.starts_with('#') {
+            break;
+        }
+        header.push(trimmed.to_owned());
+    }
+    header
+}
+
+pub fn read_header_file<P>(filename: P) -> HeaderParams
+where P: AsRef<Path>
+{
+    let reader = open_input(filename);
+    HeaderParams::from_lines(read_header_lines(reader))
+}
+
+/// A parameter that differs between the files of one glob
+#[derive(Debug, Clone)]
+pub struct ParamMismatch{
+    pub key: String,
+    pub values: BTreeSet<String>
+}
+
+impl fmt::Display for ParamMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:?}", self.key, self.values)
+    }
+}
+
+const MISSING: &str = "<missing>";
+
+/// Combines the header parameters of all files.
+/// Parameters that are expected to differ for each run (e.g. the seed) are dropped,
+/// all others have to agree. Files without any parameters are ignored
+pub fn combine_params(headers: &[(PathBuf, HeaderParams)]) -> Result<HeaderParams, Vec<ParamMismatch>>
+{
+    let headers: Vec<_> = headers.iter()
+        .filter(|(_, h)| !h.params.is_empty())
+        .collect();
+    let keys: BTreeSet<_> = headers.iter()
+        .flat_map(|(_, h)| h.params.keys())
+        .filter(|key| !is_per_file_key(key))
+        .collect();
+
+    let mut params = BTreeMap::new();
+    let mut mismatches = Vec::new();
+    for key in keys
+    {
+        let values: BTreeSet<_> = headers.iter()
+            .map(|(_, h)| h.get(key).unwrap_or(MISSING).to_owned())
+            .collect();
+        if values.len() == 1 {
+            params.insert(key.clone(), values.into_iter().next().unwrap());
+        } else {
+            mismatches.push(
+                ParamMismatch{
+                    key: key.clone(),
+                    values
+                }
+            );
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(HeaderParams{params})
+    } else {
+        Err(mismatches)
+    }
+}
+
+/// Reads the header of each file matching the glob pattern.
+/// Error, if the pattern is invalid or the parameters of the files disagree
+pub fn resolve_params(pattern: &str) -> Result<HeaderParams, String>
+{
+    let headers: Vec<_> = glob::glob(pattern)
+        .map_err(|error| format!("invalid file pattern {}: {}", pattern, error))?
+        .filter_map(Result::ok)
+        .map(|path| {
+            let params = read_header_file(&path);
+            (path, params)
+        }).collect();
+
+    let without_params = headers.iter()
+        .filter(|(_, h)| h.params.is_empty())
+        .count();
+    if without_params > 0 {
+        eprintln!("WARNING: {} of {} files have no parameters in their header", without_params, headers.len());
+    }
+
+    combine_params(&headers)
+        .map_err(
+            |mismatches|
+            {
+                let mut msg = "Parameters in file headers do not match!".to_owned();
+                for mismatch in mismatches.iter()
+                {
+                    msg.push_str(&format!("\n{}", mismatch));
+                    for (path, h) in headers.iter()
+                    {
+                        msg.push_str(&format!("\n\t{}: {}", path.display(), h.get(&mismatch.key).unwrap_or(MISSING)));
+                    }
+                }
+                msg
+            }
+        )
+}
+
+/// Uses `n` if given, otherwise N from the header.
+/// Warns if both are available but differ.
+/// Error, if neither is available
+pub fn choose_n(n: Option<usize>, params: &HeaderParams) -> Result<usize, String>
+{
+    match (n, params.n()) {
+        (Some(n), Some(header_n)) => {
+            if n != header_n {
+                eprintln!("WARNING: using N={} but file headers state N={}", n, header_n);
+            }
+            Ok(n)
+        },
+        (Some(n), None) => Ok(n),
+        (None, Some(header_n)) => {
+            println!("N={} (from file header)", header_n);
+            Ok(header_n)
+        },
+        (None, None) => Err("N not found in file headers, please specify n".to_owned())
+    }
+}
@@ -1,4 +1,6 @@
 mod parse_collect;
 mod heatmap2_worker;
+mod heatmap2_plot;
 pub use parse_collect::*;
-pub use heatmap2_worker::*;
\ No newline at end of file
+pub use heatmap2_worker::*;
+pub use heatmap2_plot::*;
\ No newline at end of file
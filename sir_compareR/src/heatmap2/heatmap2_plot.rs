@@ -0,0 +1,380 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use structopt::StructOpt;
+use crate::export::{Export, Array};
+use sampling::{HeatmapU, Histogram};
+
+/// How the counts of the heatmap are normalized before plotting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization{
+    /// raw counts
+    Counts,
+    /// every energy column sums to 1, i.e., P(feature | energy)
+    Columns,
+    /// every feature row sums to 1, i.e., P(energy | feature)
+    Rows,
+    /// all entries sum to 1
+    Global,
+    /// log10 of the column normalized heatmap, empty cells are NaN
+    Log10Columns
+}
+
+impl FromStr for Normalization {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let norm = match s.to_lowercase().as_str() {
+            "counts" | "raw" | "none" => Normalization::Counts,
+            "columns" | "column" | "col" => Normalization::Columns,
+            "rows" | "row" => Normalization::Rows,
+            "global" | "total" => Normalization::Global,
+            "log10" | "log10_columns" | "log_columns" => Normalization::Log10Columns,
+            _ => return Err("Unknown normalization. Valid: counts, columns, rows, global, log10")
+        };
+        Ok(norm)
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Normalization::Counts => "counts",
+            Normalization::Columns => "columns",
+            Normalization::Rows => "rows",
+            Normalization::Global => "global",
+            Normalization::Log10Columns => "log10"
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Command line options for plotting a heatmap of energy vs. feature, shared by Heatmap2 and Multi
+#[derive(Debug, Clone, Copy, StructOpt)]
+pub struct PlotArgs{
+    /// Use rgb color palett
+    #[structopt(long)]
+    pub rgb: bool,
+
+    /// Normalization of the heatmap.
+    /// valid: counts, columns, rows, global, log10 (log10 of column normalized).
+    /// Default: columns
+    #[structopt(long)]
+    pub norm: Option<Normalization>,
+
+    /// Overlay the conditional mean (with standard error) and median of the feature
+    /// for each energy column
+    #[structopt(long)]
+    pub overlay: bool
+}
+
+impl PlotArgs{
+    /// Normalization of the heatmap, columns if not specified
+    pub fn normalization(&self) -> Normalization
+    {
+        self.norm.unwrap_or(Normalization::Columns)
+    }
+
+    /// true, if `--norm` or `--overlay` is used.
+    /// Otherwise the column normalized heatmap is plotted by [HeatmapU::gnuplot](sampling::HeatmapU::gnuplot)
+    pub fn is_custom(&self) -> bool
+    {
+        self.norm.is_some() || self.overlay
+    }
+}
+
+/// Counts of a heatmap with energy columns and feature rows.
+/// Implemented for [HeatmapU], so the counts of the heatmap are used without copying them
+pub trait CountMatrix{
+    /// number of columns, i.e., the width of the heatmap
+    fn columns(&self) -> usize;
+
+    /// counts in row major order, i.e., index y * columns + x
+    fn counts(&self) -> &[usize];
+
+    /// number of rows, i.e., the height of the heatmap
+    fn rows(&self) -> usize
+    {
+        self.counts().len() / self.columns()
+    }
+
+    /// count of column x and row y
+    fn count(&self, x: usize, y: usize) -> f64
+    {
+        self.counts()[y * self.columns() + x] as f64
+    }
+
+    /// Adds the counts and the normalized matrix to the export.
+    /// Both have the shape (columns, rows), i.e., they are indexed by `[x, y]`
+    fn export(&self, export: &mut Export, norm: Normalization, x_axis: &str, y_axis: &str)
+    {
+        let (width, height) = (self.columns(), self.rows());
+        let x_major = |row_major: &[f64]| -> Vec<f64> {
+            (0..width)
+                .flat_map(|x| (0..height).map(move |y| row_major[y * width + x]))
+                .collect()
+        };
+        let counts: Vec<u64> = (0..width)
+            .flat_map(|x| (0..height).map(move |y| self.counts()[y * width + x] as u64))
+            .collect();
+        let shape = vec![width, height];
+        export.array("counts", &[x_axis, y_axis], Array::new(shape.clone(), counts))
+            .array("normalized", &[x_axis, y_axis], Array::new(shape, x_major(&self.normalized(norm))))
+            .info("normalization", norm);
+    }
+
+    fn column_sum(&self, x: usize) -> f64
+    {
+        (0..self.rows()).map(|y| self.count(x, y)).sum()
+    }
+
+    fn row_sum(&self, y: usize) -> f64
+    {
+        self.counts()[y * self.columns()..(y + 1) * self.columns()]
+            .iter()
+            .map(|&count| count as f64)
+            .sum()
+    }
+
+    /// Normalized matrix, row major. Entries of empty columns or rows are 0 (NaN for log10)
+    fn normalized(&self, norm: Normalization) -> Vec<f64>
+    {
+        let width = self.columns();
+        let counts = self.counts().iter().map(|&count| count as f64);
+        let div = |val: f64, sum: f64| if sum > 0.0 { val / sum } else { 0.0 };
+        match norm {
+            Normalization::Counts => counts.collect(),
+            Normalization::Global => {
+                let total: f64 = counts.clone().sum();
+                counts.map(|val| div(val, total)).collect()
+            },
+            Normalization::Rows => {
+                let sums: Vec<_> = (0..self.rows()).map(|y| self.row_sum(y)).collect();
+                counts.enumerate()
+                    .map(|(i, val)| div(val, sums[i / width]))
+                    .collect()
+            },
+            Normalization::Columns | Normalization::Log10Columns => {
+                let sums: Vec<_> = (0..width).map(|x| self.column_sum(x)).collect();
+                let iter = counts.enumerate()
+                    .map(|(i, val)| div(val, sums[i % width]));
+                if norm == Normalization::Log10Columns {
+                    iter.map(|p| if p > 0.0 { p.log10() } else { f64::NAN })
+                        .collect()
+                } else {
+                    iter.collect()
+                }
+            }
+        }
+    }
+
+    /// Conditional mean, standard error of the mean and median of the feature for each energy column.
+    /// The feature value of row y is `axis.value(y)`
+    fn column_stats(&self, axis: &ValueAxis) -> Vec<ColumnStats>
+    {
+        (0..self.columns())
+            .map(|x| {
+                let count = self.column_sum(x);
+                if count == 0.0 {
+                    return ColumnStats{
+                        count,
+                        mean: f64::NAN,
+                        error: f64::NAN,
+                        median: f64::NAN
+                    };
+                }
+                let mean = (0..self.rows())
+                    .map(|y| self.count(x, y) * axis.value(y as f64))
+                    .sum::<f64>() / count;
+                let variance = (0..self.rows())
+                    .map(|y| self.count(x, y) * (axis.value(y as f64) - mean).powi(2))
+                    .sum::<f64>() / count;
+                let mut cumulative = 0.0;
+                let median_row = (0..self.rows())
+                    .find(|&y| {
+                        cumulative += self.count(x, y);
+                        cumulative >= 0.5 * count
+                    }).unwrap();
+                ColumnStats{
+                    count,
+                    mean,
+                    error: (variance / count).sqrt(),
+                    median: axis.value(median_row as f64)
+                }
+            }).collect()
+    }
+}
+
+impl<HW, HH> CountMatrix for HeatmapU<HW, HH>
+where HW: Histogram, HH: Histogram
+{
+    fn columns(&self) -> usize
+    {
+        self.width()
+    }
+
+    fn counts(&self) -> &[usize]
+    {
+        self.heatmap()
+    }
+}
+
+/// Statistics of the feature in one energy column
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnStats{
+    pub count: f64,
+    pub mean: f64,
+    /// standard error of the mean
+    pub error: f64,
+    pub median: f64
+}
+
+/// Maps the bin index of a heatmap axis to the value of the bin center
+#[derive(Debug, Clone, Copy)]
+pub struct ValueAxis{
+    /// value of the center of the first bin
+    pub first_center: f64,
+    /// value difference between neighbouring bins
+    pub bin_width: f64,
+    /// number of bins
+    pub len: usize
+}
+
+impl ValueAxis{
+    /// Axis of bins with the given borders. The right border of integer bins is exclusive,
+    /// e.g., the integer bin [0, 2) contains 0 and 1 and its center is 0.5
+    pub fn from_borders(borders: &[f64], integer: bool) -> Self
+    {
+        let bin_width = borders[1] - borders[0];
+        let first_center = if integer {
+            borders[0] + (bin_width - 1.0) / 2.0
+        } else {
+            borders[0] + bin_width / 2.0
+        };
+        Self{
+            first_center,
+            bin_width,
+            len: borders.len() - 1
+        }
+    }
+
+    /// Axis of float bins, `borders` are the borders of the bins
+    pub fn from_f64_borders(borders: &[f64]) -> Self
+    {
+        Self::from_borders(borders, false)
+    }
+
+    /// Axis of integer bins, the right border of each bin is exclusive
+    pub fn from_usize_borders(borders: &[usize]) -> Self
+    {
+        let borders: Vec<f64> = borders.iter()
+            .map(|&border| border as f64)
+            .collect();
+        Self::from_borders(&borders, true)
+    }
+
+    /// centers of all bins
+    pub fn centers(&self) -> Vec<f64>
+    {
+        (0..self.len)
+            .map(|index| self.value(index as f64))
+            .collect()
+    }
+
+    /// value at (possibly fractional) bin index
+    pub fn value(&self, index: f64) -> f64
+    {
+        self.first_center + index * self.bin_width
+    }
+
+    pub(crate) fn write_tics<W: Write>(&self, mut writer: W, axis: &str, tics: usize) -> std::io::Result<()>
+    {
+        let last = (self.len - 1) as f64;
+        let tics: Vec<_> = (0..tics)
+            .map(|i| {
+                let pos = last * i as f64 / (tics - 1) as f64;
+                let label = (self.value(pos) * 1000.0).round() / 1000.0;
+                format!("\"{}\" {}", label, pos)
+            }).collect();
+        writeln!(writer, "set {}tics ({})", axis, tics.join(", "))
+    }
+}
+
+/// Writes a gnuplot script plotting the heatmap of energy vs. feature,
+/// normalized according to [PlotArgs::normalization].
+/// With `args.overlay` the [ColumnStats] are written as data block `$overlay` and plotted on top
+pub fn write_heatmap_gnuplot<W: Write, M: CountMatrix>(
+    mut writer: W,
+    output_name: &str,
+    matrix: &M,
+    energy_axis: ValueAxis,
+    feature_axis: ValueAxis,
+    y_label: &str,
+    args: PlotArgs
+) -> std::io::Result<()>
+{
+    let norm = args.normalization();
+    writeln!(writer, "#normalization: {}", norm)?;
+    writeln!(writer, "set t pdfcairo")?;
+    writeln!(writer, "set output \"{}.pdf\"", output_name)?;
+    writeln!(writer, "set xlabel \"E\"")?;
+    writeln!(writer, "set ylabel \"{}\"", y_label)?;
+    let (width, height) = (matrix.columns(), matrix.rows());
+    writeln!(writer, "set xrange[-0.5:{}]", width as f64 - 0.5)?;
+    writeln!(writer, "set yrange[-0.5:{}]", height as f64 - 0.5)?;
+    energy_axis.write_tics(&mut writer, "x", 5.min(width).max(2))?;
+    feature_axis.write_tics(&mut writer, "y", 5.min(height).max(2))?;
+    if args.rgb {
+        writeln!(writer, "set palette rgbformulae 7,5,15")?;
+    } else {
+        writeln!(writer, "set palette cubehelix start 0.5 cycles -1.5 saturation 1")?;
+        writeln!(writer, "set palette gamma 0.65")?;
+    }
+    if norm == Normalization::Log10Columns {
+        writeln!(writer, "set cblabel \"log10 P\"")?;
+    }
+
+    let normalized = matrix.normalized(norm);
+    writeln!(writer, "$data << EOD")?;
+    for row in normalized.chunks(width)
+    {
+        let row: Vec<_> = row.iter().map(|val| val.to_string()).collect();
+        writeln!(writer, "{}", row.join(" "))?;
+    }
+    writeln!(writer, "EOD")?;
+
+    if args.overlay {
+        writeln!(writer, "#column energy count mean error median")?;
+        writeln!(writer, "$overlay << EOD")?;
+        for (x, stats) in matrix.column_stats(&feature_axis).into_iter().enumerate()
+        {
+            writeln!(
+                writer,
+                "{} {} {} {} {} {}",
+                x,
+                energy_axis.value(x as f64),
+                stats.count,
+                stats.mean,
+                stats.error,
+                stats.median
+            )?;
+        }
+        writeln!(writer, "EOD")?;
+        // feature values are mapped to row indices of the heatmap
+        let to_row = |col: usize| format!(
+            "((${} - {}) / {})",
+            col,
+            feature_axis.first_center,
+            feature_axis.bin_width
+        );
+        writeln!(
+            writer,
+            "plot $data matrix with image t \"\", \\\n     $overlay using 1:{}:($5 / {}) with yerrorlines lc rgb \"white\" t \"mean\", \\\n     $overlay using 1:{} with lines lc rgb \"red\" dt 2 t \"median\"",
+            to_row(4),
+            feature_axis.bin_width,
+            to_row(6)
+        )?;
+    } else {
+        writeln!(writer, "plot $data matrix with image t \"\"")?;
+    }
+    writeln!(writer, "set output")
+}
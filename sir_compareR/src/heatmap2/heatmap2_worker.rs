@@ -2,22 +2,37 @@ use num_traits::{Zero, AsPrimitive};
 use ord_subset::{OrdSubset, OrdSubsetIterExt};
 use std::str::FromStr;
 use crate::heatmap2::*;
+use crate::energy::EnergyAxis;
+use crate::expression::{Expression, Curve};
+use crate::crossing::*;
+use crate::auto_range::AutoRange;
+use std::sync::atomic::*;
 use sampling::*;
 use either::*;
 use std::fmt;
 use fmt::Display;
-use std::any::{TypeId, Any};
 
-#[derive(Debug, Clone, Copy)]
+/// Feature that is extracted from each curve
+#[derive(Debug, Clone)]
 pub enum FunctionChooser{
+    /// maximum of the curve
     ValMax,
+    /// minimum of the curve
     ValMin,
+    /// time step of the maximum
     IndexMax,
+    /// time step of the minimum
     IndexMin,
+    /// last time step with a value that is not zero
     LastIndexNotZero,
+    /// time steps between reaching x times the maximum and y times the maximum
     FromXToY(f64, f64),
-    /// sum for usize, otherwise sum * energy (fraction)
-    Sum
+    /// time at which the curve crosses a threshold, see [Crossing]
+    Crossing(Crossing),
+    /// sum for usize, otherwise sum / energy (fraction)
+    Sum,
+    /// expression over the features, see [Expression]
+    Expression(Expression)
 }
 
 impl Display for FunctionChooser {
@@ -25,7 +40,13 @@ impl Display for FunctionChooser {
         match self {
             Self::FromXToY(x, y) => {
                 write!(f, "from_{}_to_{}", x, y)
-            }
+            },
+            Self::Expression(expr) => {
+                write!(f, "{}", expr.file_name())
+            },
+            Self::Crossing(crossing) => {
+                write!(f, "{}", crossing)
+            },
             _ => {
                 write!(f, "{:?}", self)
             }
@@ -33,96 +54,195 @@ impl Display for FunctionChooser {
     }
 }
 
-// this will be optimized out: https://godbolt.org/z/jdvdxjYhn
-#[inline]
-pub fn cast_or_panic<T, U>(val: U) -> T
-where U: Any, 
-T: Any
-{
-    if TypeId::of::<T>() == TypeId::of::<U>()
+impl FunctionChooser{
+    /// Has to be called before evaluating expressions containing `n`
+    pub fn bind_n(&mut self, n: usize)
     {
-        let b: Box<dyn Any> = Box::new(val);
-        *b.downcast().unwrap()
-    } else {
-        panic!("Issue with casting - you probably did not mean to normalize the trajectories")
+        if let Self::Expression(expr) = self {
+            expr.bind_n(n);
+        }
     }
-}
 
-impl FunctionChooser{
-    pub fn f64_exec<I>(&self, iter: I, energy: usize) -> f64
-    where I: Iterator<Item=f64>
+    /// true for features that are floats in general, i.e., expressions.
+    /// They should be calculated with [f64_exec](Self::f64_exec)
+    pub fn is_float(&self) -> bool
+    {
+        matches!(self, Self::Expression(_))
+    }
+
+    /// Label for plots, e.g. the y label of the heatmap
+    pub fn label(&self) -> String
     {
         match self {
-            FunctionChooser::ValMax => max_val(iter),
-            FunctionChooser::Sum => iter.sum::<f64>() / energy as f64,
-            _ => unimplemented!()
+            Self::Expression(expr) => expr.to_string(),
+            _ => self.to_string()
         }
     }
 
-    pub fn usize_exec<I, T>(&self, iter: I) -> usize
-    where I: Iterator<Item=T> + Clone,
-     T: Copy + OrdSubset + PartialEq + std::fmt::Debug + Any + 'static + Send + Sync + Zero + AsPrimitive<f64>,
+    /// Feature of a curve with float values, e.g. a normalized curve.
+    /// Index features are returned as float, crossing times are interpolated.
+    /// Returns None and increments [MISS_COUNTER], if the feature is not defined for the curve,
+    /// e.g. if the curve does not cross the threshold.
+    /// The extinction index is only used by expressions
+    pub fn f64_exec<I>(&self, iter: I, energy: usize, extinction_index: Option<usize>) -> Option<f64>
+    where I: Iterator<Item=f64> + Clone
     {
-        match self {
-            FunctionChooser::ValMax => {
-                cast_or_panic(max_val::<T, _>(iter))
+        let res = match self {
+            FunctionChooser::ValMax => Some(max_val(iter)),
+            FunctionChooser::ValMin => Some(min_val(iter)),
+            FunctionChooser::IndexMax => Some(max_index(iter) as f64),
+            FunctionChooser::IndexMin => Some(min_index(iter) as f64),
+            FunctionChooser::LastIndexNotZero => Some(last_index_not_zero(iter) as f64),
+            FunctionChooser::FromXToY(x, y) => {
+                let values: Vec<f64> = iter.collect();
+                let (from, to) = from_x_to_y(*x, *y);
+                from.time(&values)
+                    .and_then(|t_x| to.time(&values).map(|t_y| t_y - t_x))
             },
-            FunctionChooser::IndexMax => max_index::<T, _>(iter),
-            FunctionChooser::IndexMin => min_index::<T, _>(iter),
-            FunctionChooser::ValMin => {
-                cast_or_panic(min_val::<T, _>(iter))
+            FunctionChooser::Crossing(crossing) => {
+                let values: Vec<f64> = iter.collect();
+                crossing.time(&values)
             },
             FunctionChooser::Sum => {
-                let iter = iter.map(|val| cast_or_panic::<usize, _>(val));
-                iter.sum()
-            },
-            FunctionChooser::LastIndexNotZero => {
-                let mut index = 0;
-                for (id, val) in iter.enumerate() {
-                    if val != T::zero() {
-                        index = id; 
-                    }
+                if energy == 0 {
+                    None
+                } else {
+                    Some(iter.sum::<f64>() / energy as f64)
                 }
-                index
             },
+            FunctionChooser::Expression(expr) => {
+                Some(eval_expression(expr, iter, energy, extinction_index))
+                    .filter(|val| !val.is_nan())
+            }
+        };
+        count_miss(res)
+    }
+
+    /// Feature of a curve as integer.
+    /// If the curve has float values (e.g. normalized curves), 
+    /// value features (ValMax, ValMin, Sum) are only defined if they are non-negative integers.
+    /// Crossing times are the first time step at or after the crossing.
+    /// Expressions are only defined if the result is a non-negative integer, see [is_float](Self::is_float).
+    /// Returns None and increments [MISS_COUNTER], if the feature is not defined for the curve.
+    /// The extinction index is only used by expressions
+    pub fn usize_exec<I, T>(&self, iter: I, energy: usize, extinction_index: Option<usize>) -> Option<usize>
+    where I: Iterator<Item=T> + Clone,
+     T: Copy + OrdSubset + PartialEq + Zero + AsPrimitive<f64> + AsPrimitive<usize>,
+    {
+        let res = match self {
+            FunctionChooser::ValMax => checked_usize(max_val(iter).as_()),
+            FunctionChooser::IndexMax => Some(max_index(iter)),
+            FunctionChooser::IndexMin => Some(min_index(iter)),
+            FunctionChooser::ValMin => checked_usize(min_val(iter).as_()),
+            FunctionChooser::Sum => {
+                let sum = iter.map(|val| AsPrimitive::<f64>::as_(val))
+                    .sum();
+                checked_usize(sum)
+            },
+            FunctionChooser::LastIndexNotZero => Some(last_index_not_zero(iter)),
             FunctionChooser::FromXToY(x, y) => {
-                let max_val = max_val(iter.clone());
-                let p_y = max_val.as_() * y;
-                let p_x = max_val.as_() * x;
-                let mut index_p_x = None;
-                let mut val_p_x = 0.0;
-                let mut index_p_y = None;
-                for (index, val) in iter.clone().enumerate()
-                {
-                    let val = val.as_();
-                    if val >= p_x {
-                        index_p_x = Some(index);
-                        val_p_x = val as f64;
-                        break;
-                    }
-                }
-                for (index, val) in iter.enumerate()
-                {
-                    if val.as_() >= p_y {
-                        index_p_y = Some(index);
-                        break;
-                    }
-                }
-                if index_p_y.is_none() {
-                    dbg!(index_p_x);
-                    dbg!(max_val);
-                    dbg!(val_p_x);
-                    dbg!(p_y);
-                }
-                index_p_y.unwrap() - index_p_x.unwrap()
+                let values: Vec<f64> = iter.map(|val| val.as_()).collect();
+                let (from, to) = from_x_to_y(*x, *y);
+                from.index(&values)
+                    .and_then(
+                        |index_x| to.index(&values)
+                            .and_then(|index_y| index_y.checked_sub(index_x))
+                    )
+            },
+            FunctionChooser::Crossing(crossing) => {
+                let values: Vec<f64> = iter.map(|val| val.as_()).collect();
+                crossing.index(&values)
+            },
+            FunctionChooser::Expression(expr) => {
+                let iter = iter.map(|val| AsPrimitive::<f64>::as_(val));
+                checked_usize(eval_expression(expr, iter, energy, extinction_index))
             }
+        };
+        count_miss(res)
+    }
+}
+
+/// Number of curves for which the [FunctionChooser] was not defined
+pub static MISS_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn count_miss<T>(res: Option<T>) -> Option<T>
+{
+    if res.is_none() {
+        MISS_COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+    res
+}
+
+/// Prints and resets the number of misses, see [MISS_COUNTER]
+pub fn report_misses()
+{
+    let misses = MISS_COUNTER.swap(0, Ordering::Relaxed);
+    if misses > 0 {
+        println!("MISSES: {} curves did not cross the threshold or the feature was undefined", misses);
+    }
+}
+
+/// `val` as integer. None, if it is negative, not integral or too large
+fn checked_usize(val: f64) -> Option<usize>
+{
+    if val >= 0.0 && val.fract() == 0.0 && val < usize::MAX as f64 {
+        Some(val as usize)
+    } else {
+        None
+    }
+}
+
+/// crossings of x times and y times the maximum on the rising flank
+fn from_x_to_y(x: f64, y: f64) -> (Crossing, Crossing)
+{
+    let crossing = |fraction| Crossing{
+        flank: Flank::Rising,
+        threshold: Threshold::Relative(fraction)
+    };
+    (crossing(x), crossing(y))
+}
+
+fn eval_expression<I>(expr: &Expression, iter: I, energy: usize, extinction_index: Option<usize>) -> f64
+where I: Iterator<Item=f64>
+{
+    let values: Vec<f64> = iter.collect();
+    let curve = Curve{
+        values: &values,
+        energy,
+        extinction_index
+    };
+    expr.eval(&curve)
+}
+
+fn last_index_not_zero<T, I>(iter: I) -> usize
+where I: Iterator<Item=T>,
+    T: Zero + PartialEq
+{
+    let mut index = 0;
+    for (id, val) in iter.enumerate() {
+        if val != T::zero() {
+            index = id; 
         }
     }
+    index
 }
 
+/// parses 'x to y', e.g. '0.1 to 0.9'
+fn parse_x_to_y(s: &str) -> Option<Result<(f64, f64), &'static str>>
+{
+    let words: Vec<_> = s.split_whitespace().collect();
+    if words.len() != 3 || words[1] != "to" {
+        return None;
+    }
+    let x = words[0].parse::<f64>()
+        .map_err(|_| "Invalid FunctionChooser requested - unable to parse first (x) number");
+    let y = words[2].parse::<f64>()
+        .map_err(|_| "Invalid FunctionChooser requested - unable to parse second (y) number");
+    Some(x.and_then(|x| y.map(|y| (x, y))))
+}
 
 impl FromStr for FunctionChooser {
-    type Err = &'static str;
+    type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         
         match s.to_lowercase().as_str() {
@@ -132,24 +252,19 @@ impl FromStr for FunctionChooser {
             "val_min" | "valmin" => Ok(FunctionChooser::ValMin),
             "lastindexnotzero" | "last_index_not_zero" | "last-index-not-zero" | "last" => Ok(FunctionChooser::LastIndexNotZero),
             "sum" => Ok(FunctionChooser::Sum),
+            lower if lower.starts_with("cross_") => {
+                lower.parse::<Crossing>()
+                    .map(Self::Crossing)
+                    .map_err(|e| e.to_owned())
+            },
             _ => {
-                if s.contains("to") {
-                    let mut iter = s.split_whitespace();
-                    let x = iter.next()
-                        .ok_or("Invalid FunctionChooser requested - invalid first (x) number")?;
-                    if let Some(n) = iter.next(){
-                        assert_eq!(n, "to", "Invalid_request - no to?");
-                    }
-                    let y = iter.next()
-                        .ok_or("Invalid FunctionChooser requested - invalid second (y) number")?;
-                    
-                    let x = x.parse::<f64>()
-                        .map_err(|_| "Invalid FunctionChooser requested - unable to parse first (x) number")?;
-                    let y = y.parse::<f64>()
-                        .map_err(|_| "Invalid FunctionChooser requested - unable to parse second (y) number")?;
+                if let Some(res) = parse_x_to_y(s) {
+                    let (x, y) = res?;
                     return Ok(Self::FromXToY(x, y));
                 }
-                Err("Invalid FunctionChooser requested")
+                s.parse::<Expression>()
+                    .map(Self::Expression)
+                    .map_err(|e| format!("Invalid FunctionChooser requested: {}", e))
             }
         }
     }
@@ -211,6 +326,7 @@ where I: Iterator<Item=T> + Clone,
 }
 
 
+/// Histogram of the feature, i.e., the y axis of the heatmap
 #[derive(Debug, Clone)]
 pub enum HeatmapBuilder{
     F64Heatmap {
@@ -222,7 +338,11 @@ pub enum HeatmapBuilder{
         bins: usize,
         left: usize,
         right: usize,
-    }
+    },
+    /// float heatmap with range determined from the data, see [HeatmapBuilder::resolve]
+    F64Auto(AutoRange),
+    /// integer heatmap with range determined from the data, see [HeatmapBuilder::resolve]
+    UsizeAuto(AutoRange)
 }
 
 impl fmt::Display for HeatmapBuilder {
@@ -236,6 +356,12 @@ impl fmt::Display for HeatmapBuilder {
             HeatmapBuilder::F64Heatmap{bins, left, right} =>{
                 write!(f, "Hf64")?;
                 write!(f, "bins{}left{}right{}", bins, left, right)
+            },
+            HeatmapBuilder::F64Auto(range) => {
+                write!(f, "Hf64bins{}auto{}", range.bins, range.clip)
+            },
+            HeatmapBuilder::UsizeAuto(range) => {
+                write!(f, "Husizebins{}auto{}", range.bins, range.clip)
             }
         }
         
@@ -252,6 +378,17 @@ impl FromStr for HeatmapBuilder {
         let bins = iter.next().expect("Not enough arguments, bins missing");
         let bins = bins.parse::<usize>().unwrap();
         let left = iter.next().expect("Not enough arguments, left missing");
+
+        if left == "auto" {
+            let range = AutoRange::parse(bins, iter)?;
+            return if lower.starts_with('u') {
+                Ok(Self::UsizeAuto(range))
+            } else if lower.starts_with('f') {
+                Ok(Self::F64Auto(range))
+            } else {
+                Err("Usage: 'f bins auto [clip]'. Choose either u for unsigned (integer) or f for floats. E.g. 'u 100 auto 0.01'")
+            };
+        }
         let right = iter.next().expect("Not enough arguments, right missing");
 
         assert_eq!(iter.next(), None, "HeatmapBuilder: To many arguments");
@@ -286,10 +423,10 @@ impl FromStr for HeatmapBuilder {
 }
 
 impl HeatmapBuilder{
-    pub fn build(&self, n: usize, energy_bins: usize) -> EitherH
+    /// Heatmap of energy (x axis) vs. feature (y axis)
+    pub fn build(&self, energy: &EnergyAxis) -> EitherH
     {
-        let energy_hist = HistUsize::new(1, n + 1, energy_bins)
-            .expect("failed to create outer hist");
+        let energy_hist = energy.hist();
 
         match self{
             Self::F64Heatmap{bins, left, right} => 
@@ -304,8 +441,47 @@ impl HeatmapBuilder{
                     .expect("failed to create fun hist");
                 let heatmap = HeatmapU::new(energy_hist, fun_hist);
                 Right(heatmap)
+            },
+            Self::F64Auto(_) | Self::UsizeAuto(_) => {
+                unreachable!("auto range has to be resolved before building the heatmap")
             }
         }
         
     }
+
+    /// Range is determined from the data, see [HeatmapBuilder::resolve]
+    pub fn is_auto(&self) -> bool
+    {
+        matches!(self, Self::F64Auto(_) | Self::UsizeAuto(_))
+    }
+
+    /// Replaces an auto range by the range of the `values` of the feature and reports it.
+    /// Fixed ranges are returned unchanged
+    pub fn resolve(&self, values: Vec<f64>) -> Result<Self, String>
+    {
+        match self {
+            Self::F64Auto(range) => {
+                let (left, right) = range.f64_range(values)?;
+                range.report("feature", left, right);
+                Ok(Self::F64Heatmap{
+                    bins: range.bins,
+                    left,
+                    right
+                })
+            },
+            Self::UsizeAuto(range) => {
+                let (left, right) = range.int_range(values)?;
+                // values of integer features are never negative
+                let left = left as usize;
+                let right = right as usize;
+                range.report("feature", left, right);
+                Ok(Self::UsizeHeatmap{
+                    bins: range.bins,
+                    left,
+                    right
+                })
+            },
+            _ => Ok(self.clone())
+        }
+    }
 }
\ No newline at end of file
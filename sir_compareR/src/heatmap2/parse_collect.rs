@@ -1,8 +1,5 @@
-use crate::Heatmap2Opts;
-use lzma::LzmaReader;
-use flate2::read::*;
+use crate::input::open_input;
 use std::io::*;
-use std::fs::*;
 use sampling::*;
 use std::result::Result;
 use std::path::{Path, PathBuf};
@@ -11,28 +8,149 @@ use rayon::prelude::*;
 use crate::heatmap2::*;
 use either::*;
 use std::sync::atomic::*;
+use crate::expression::{Expression, Curve, is_true};
 
 pub type HeatmapUF = HeatmapU<HistUsize, HistF64>;
 pub type HeatmapUU = HeatmapU<HistUsize, HistUsize>;
+/// Heatmap of energy vs. float or integer feature
 pub type EitherH = Either<HeatmapUF, HeatmapUU>;
 
 pub static WARNING_PRINTED: AtomicBool = AtomicBool::new(false);
 
+/// true after the first invalid data line was reported, see [CurveLine::parse]
+pub static INVALID_LINE_PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of curves removed by the `--where` filter
+pub static FILTERED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Which feature is extracted from which curves,
+/// independent of the command line options it is built from
+#[derive(Debug, Clone)]
+pub struct FeatureSpec{
+    pub fun: FunctionChooser,
+    /// float feature, integer feature otherwise
+    pub float: bool,
+    /// divide each curve by its maximum before extracting the feature
+    pub normed: bool,
+    /// curves not fulfilling the filter are skipped
+    pub filter: Option<Expression>,
+    /// only every `every`th curve is used
+    pub every: usize
+}
+
+impl FeatureSpec{
+    /// feature of the curve, as float for either kind of feature
+    pub(crate) fn feature(&self, curve: &CurveLine) -> Option<f64>
+    {
+        if self.float {
+            f64_feature(curve, &self.fun, self.normed)
+        } else {
+            usize_feature(curve, &self.fun, self.normed)
+                .map(|val| val as f64)
+        }
+    }
+
+    /// false, if the curve does not fulfill the filter, see [keep_curve]
+    pub(crate) fn keep(&self, curve: &CurveLine) -> bool
+    {
+        keep_curve(curve, self.filter.as_ref())
+    }
+}
+
+/// Energy, extinction index and values of the curve in one data line
+#[derive(Debug, Clone)]
+pub(crate) struct CurveLine{
+    pub energy: usize,
+    /// None if the extinction index is not a number
+    pub extinction_index: Option<usize>,
+    pub values: Vec<f64>
+}
+
+impl CurveLine{
+    /// Parses a data line `energy extinction_index values...`.
+    /// Invalid lines are counted as misses (see [MISS_COUNTER]), the first one is reported
+    pub(crate) fn parse(slice: &str) -> Option<Self>
+    {
+        match Self::try_parse(slice) {
+            Ok(curve) => Some(curve),
+            Err(error) => {
+                if !INVALID_LINE_PRINTED.swap(true, Ordering::Relaxed) {
+                    eprintln!("WARNING: {}. Invalid lines are skipped and counted as misses", error);
+                }
+                MISS_COUNTER.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn try_parse(slice: &str) -> Result<Self, String>
+    {
+        let invalid = || {
+            let start: String = slice.chars().take(40).collect();
+            format!("invalid line starting with '{}'", start)
+        };
+        let mut it = slice.split_whitespace();
+        let energy = it.next()
+            .and_then(|v| v.parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        let extinction_index = it.next()
+            .ok_or_else(invalid)?
+            .parse::<usize>()
+            .ok();
+        let values = it.map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        Ok(
+            Self{
+                energy,
+                extinction_index,
+                values
+            }
+        )
+    }
+
+    /// the curve as seen by expressions
+    pub(crate) fn curve(&self) -> Curve<'_>
+    {
+        Curve{
+            values: &self.values,
+            energy: self.energy,
+            extinction_index: self.extinction_index
+        }
+    }
+}
+
+/// false, if the curve does not fulfill the filter.
+/// The filter is evaluated for the curve as stored, i.e., before norming
+pub(crate) fn keep_curve(curve: &CurveLine, filter: Option<&Expression>) -> bool
+{
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return true
+    };
+    let keep = is_true(filter.eval(&curve.curve()));
+    if !keep {
+        FILTERED_COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+    keep
+}
+
 fn parse_and_count<R>
 (
     reader: R, 
     every: usize, 
     either_heatmap: &mut EitherH,
-    reduce: FunctionChooser,
+    reduce: &FunctionChooser,
     normed: bool,
+    filter: Option<&Expression>
 )
 where
     R: Read,
 {
     let buf_reader = BufReader::new(reader);
     
-    let f64_heatmap_fun = |slice: &str, heatmap: &mut HeatmapUF| parse_into_heatmap_f64(slice, heatmap, reduce, normed);
-    let usize_heatmap_fun = |slice: &str, heatmap: &mut HeatmapUU| parse_into_heatmap_usize(slice, heatmap, reduce, normed);
+    let f64_heatmap_fun = |curve: &CurveLine, heatmap: &mut HeatmapUF| parse_into_heatmap_f64(curve, heatmap, reduce, normed);
+    let usize_heatmap_fun = |curve: &CurveLine, heatmap: &mut HeatmapUU| parse_into_heatmap_usize(curve, heatmap, reduce, normed);
 
 
     buf_reader.lines()
@@ -43,108 +161,131 @@ where
                 && !line.is_empty()
             }
         ).step_by(every)
-        .for_each( |line|
+        .filter_map(|line| CurveLine::parse(line.trim()))
+        .filter(|curve| keep_curve(curve, filter))
+        .for_each( |curve|
             {
-                let slice = line.trim();
-                
                 either_heatmap.as_mut()
                     .either_with
                     (
-                        slice,
+                        &curve,
                         f64_heatmap_fun,
                         usize_heatmap_fun
                     );
-                
-                
             }
         );
 }
 
 pub(crate) fn parse_into_heatmap_f64
 (
-    slice: &str,
+    curve: &CurveLine,
     heatmap: &mut HeatmapUF,
-    fun: FunctionChooser,
+    fun: &FunctionChooser,
     normed: bool
 )
 {
-    let mut it = slice.split_whitespace();
-    let energy = it.next().unwrap();
-    
-    let energy = energy.parse::<usize>().unwrap();
-
-    let iter = slice
-        .split_whitespace()
-        .skip(2)
-        .map(|v| v.parse::<f64>().unwrap());
+    if let Some(val) = f64_feature(curve, fun, normed) {
+        let _ = heatmap.count(curve.energy, val);
+    }
+}
 
+/// float feature of the curve.
+/// NaN features, e.g. normed curves with maximum 0, are dropped
+pub(crate) fn f64_feature(curve: &CurveLine, fun: &FunctionChooser, normed: bool) -> Option<f64>
+{
+    let iter = curve.values.iter().copied();
     let val = if normed {
-        let max = max_val(iter);
-        let iter = slice
-            .split_whitespace()
-            .skip(2)
-            .map(|v| v.parse::<f64>().unwrap() / max);
-        fun.f64_exec(iter, energy)
+        let max = max_val(iter.clone());
+        fun.f64_exec(iter.map(|v| v / max), curve.energy, curve.extinction_index)
     } else {
-        fun.f64_exec(iter, energy)
+        fun.f64_exec(iter, curve.energy, curve.extinction_index)
     };
-
-    let _ = heatmap.count(energy, val);
+    val.filter(|val| !val.is_nan())
 }
 
 
 pub(crate) fn parse_into_heatmap_usize
 (
-    slice: &str,
+    curve: &CurveLine,
     heatmap: &mut HeatmapUU,
-    fun: FunctionChooser,
+    fun: &FunctionChooser,
     normed: bool
 )
 {
-    let mut it = slice.split_whitespace();
-    let energy = it.next().unwrap();
-    
-    let energy = energy.parse::<usize>().unwrap();
+    if let Some(val) = usize_feature(curve, fun, normed) {
+        let _ = heatmap.count(curve.energy, val);
+    }
+}
 
-    let iter = slice
-        .split_whitespace()
-        .skip(2)
-        .map(|v| v.parse::<usize>().unwrap());
+/// Warns once, that integer features of normed curves are probably a mistake
+pub(crate) fn warn_normed_integer()
+{
+    if !WARNING_PRINTED.swap(true, Ordering::SeqCst){
+        eprintln!("Are you sure that you want to norm the curves? This is probably a mistake!");
+    }
+}
 
-    let val = if normed {
-        if !WARNING_PRINTED.swap(true, Ordering::SeqCst){
-            eprintln!("Are you sure that you want to norm the curves? This is probably a mistake!");
-        }
-        let max = max_val(iter) as f64;
-        let iter = slice
-            .split_whitespace()
-            .skip(2)
-            .map(|v| v.parse::<f64>().unwrap() / max);
-        fun.usize_exec(iter)
+/// integer feature of the curve
+pub(crate) fn usize_feature(curve: &CurveLine, fun: &FunctionChooser, normed: bool) -> Option<usize>
+{
+    let iter = curve.values.iter().copied();
+    if normed {
+        warn_normed_integer();
+        let max = max_val(iter.clone());
+        fun.usize_exec(iter.map(|v| v / max), curve.energy, curve.extinction_index)
     } else {
-        fun.usize_exec(iter)
-    };
+        fun.usize_exec(iter, curve.energy, curve.extinction_index)
+    }
+}
 
-    let _ = heatmap.count(energy, val);
+/// Feature values of all curves of all files matching `files`, used to determine an auto range.
+/// Files are parsed in parallel
+pub fn collect_feature_values(files: &str, spec: &FeatureSpec) -> Vec<f64>
+{
+    let files: Vec<_> = glob::glob(files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+
+    println!("determining range");
+    let values = files.par_iter()
+        .progress()
+        .flat_map_iter(|entry|
+            {
+                let buf_reader = BufReader::new(open_input(entry));
+                let values: Vec<f64> = buf_reader.lines()
+                    .map(|v| v.unwrap())
+                    .filter(|line| !line.trim_start().starts_with('#') && !line.is_empty())
+                    .step_by(spec.every)
+                    .filter_map(|line| CurveLine::parse(line.trim()))
+                    .filter(|curve| spec.keep(curve))
+                    .filter_map(|curve| spec.feature(&curve))
+                    .collect();
+                values
+            }
+        ).collect();
+    // misses and filtered curves are reported by the counting pass
+    MISS_COUNTER.store(0, Ordering::Relaxed);
+    FILTERED_COUNTER.store(0, Ordering::Relaxed);
+    values
 }
 
-pub fn parse_and_count_all_files(opts: &Heatmap2Opts) -> (Vec<PathBuf>, EitherH)
+/// Counts the feature of each curve of all files matching `files` into the empty `heatmap_origin`.
+/// Files are parsed in parallel
+pub fn parse_and_count_all_files(files: &str, spec: &FeatureSpec, mut heatmap_origin: EitherH) -> (Vec<PathBuf>, EitherH)
 {
     
-    let files: Vec<_> = glob::glob(&opts.files)
+    let files: Vec<_> = glob::glob(files)
         .unwrap()
         .filter_map(Result::ok)
         .collect();
     
-    let mut heatmap_origin = opts.heatmap_builder
-        .build(opts.n, opts.bins);
-    
     let heatmaps: Vec<_> = files.par_iter()
         .progress()
         .map(|entry|
             {
                 let mut heatmap = heatmap_origin.clone();
-                parse_and_count_file(entry, opts.every, &mut heatmap, opts.fun, opts.normed);
+                parse_and_count_file(entry, spec.every, &mut heatmap, &spec.fun, spec.normed, spec.filter.as_ref());
                 heatmap
             }
         ).collect();
@@ -162,6 +303,7 @@ pub fn parse_and_count_all_files(opts: &Heatmap2Opts) -> (Vec<PathBuf>, EitherH)
             }
         }
     }
+    report_misses();
     (files, heatmap_origin)
 }
 
@@ -172,30 +314,13 @@ pub fn parse_and_count_file<P>
     filename: P,
     every: usize,
     heatmap: &mut EitherH,
-    hist_reduce: FunctionChooser,
-    normed: bool
+    hist_reduce: &FunctionChooser,
+    normed: bool,
+    filter: Option<&Expression>
 )
 where P: AsRef<Path>,
 {
-    let ending = filename.as_ref()
-        .extension()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let file = File::open(&filename).unwrap();
-
-    match ending {
-        "gz" => {
-            let decoder = GzDecoder::new(file);
-            parse_and_count(decoder, every, heatmap, hist_reduce, normed)
-        },
-        "xz" => {
-            let decoder = LzmaReader::new_decompressor(file).unwrap();
-            parse_and_count(decoder, every, heatmap, hist_reduce, normed)
-        },
-        _ => {
-            parse_and_count(file, every, heatmap, hist_reduce, normed)
-        }
-    }
+    let reader = open_input(filename);
+    parse_and_count(reader, every, heatmap, hist_reduce, normed, filter)
 
 }
\ No newline at end of file
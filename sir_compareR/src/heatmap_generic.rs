@@ -1,6 +1,14 @@
 mod helper;
 mod analyzer;
+mod weighted;
+mod generic_hist;
+mod matrix_plot;
+mod hist3d;
 
 use helper::*;
 pub use analyzer::*;
-pub use helper::*;
\ No newline at end of file
+pub use helper::*;
+pub use weighted::*;
+pub use generic_hist::*;
+pub use matrix_plot::*;
+pub use hist3d::*;
\ No newline at end of file
@@ -1,20 +1,31 @@
 use crate::heatmap_generic::*;
 use sampling::*;
-use std::{fmt::Display, fs::*, io::{BufRead, BufReader, BufWriter, Read, Write}, process::Command, str::FromStr};
-use lzma::LzmaReader;
-use flate2::read::*;
+use std::{fmt::Display, fs::*, io::{BufRead, BufReader, BufWriter, Read, Write}, process::Command};
+use std::sync::atomic::*;
+use crate::input::open_input;
 use num_traits::AsPrimitive;
 use crate::stats;
+use crate::export::{Export, Array};
+use crate::heatmap2::{CountMatrix, Normalization, ValueAxis};
+use std::path::{Path, PathBuf};
+use indicatif::*;
+use rayon::prelude::*;
+use crate::expression::{ColumnExpression, is_true};
 
 
-pub fn generate_heatmap(opts: HeatmapGenericOpts)
+pub fn generate_heatmap(mut opts: HeatmapGenericOpts) -> Result<Vec<String>, String>
 {
+    if opts.hist_x.is_auto() || opts.hist_y.is_auto() {
+        let (x_values, y_values) = collect_columns(&opts);
+        opts.hist_x = opts.hist_x.resolve("x", x_values)?;
+        opts.hist_y = opts.hist_y.resolve("y", y_values)?;
+    }
     let hist_x = opts.hist_x.build()
         .expect("Error during histogram X build!");
     let hist_y = opts.hist_y.build()
         .expect("Error during histogram Y build!");
 
-    match hist_x {
+    let outputs = match hist_x {
         HistWrapper::Isize{hist} => {
             let hist_x = hist;
             match hist_y{
@@ -41,73 +52,59 @@ pub fn generate_heatmap(opts: HeatmapGenericOpts)
                 }
             }
         }
-    }
+    };
+    Ok(outputs)
 }
 
 pub fn work<X, Y, HX, HY>(
     opts: HeatmapGenericOpts,
     hist_x: HX,
     hist_y: HY
-)
-    where HX: Histogram + HistogramVal<X>,
-    HY: Histogram + HistogramVal<Y>,
-    X: FromStr + AsPrimitive<f64> + Display,
-    Y: FromStr + AsPrimitive<f64> + Display
-{
-    let borders = hist_x.borders_clone().unwrap();
-    let x_min = borders.first().unwrap().as_();
-    let x_max = borders.last().unwrap().as_();
-    let borders = hist_y.borders_clone().unwrap();
-    let y_min = borders.first().unwrap().as_();
-    let y_max = borders.last().unwrap().as_();
+) -> Vec<String>
+    where HX: Histogram + HistogramVal<X> + Clone + Send + Sync,
+    HY: Histogram + HistogramVal<Y> + Clone + Send + Sync,
+    X: AsPrimitive<f64> + Display,
+    Y: AsPrimitive<f64> + Display,
+    f64: AsPrimitive<X> + AsPrimitive<Y>
+{
+    let x_edges: Vec<f64> = hist_x.borders_clone()
+        .unwrap()
+        .into_iter()
+        .map(|border| border.as_())
+        .collect();
+    let y_edges: Vec<f64> = hist_y.borders_clone()
+        .unwrap()
+        .into_iter()
+        .map(|border| border.as_())
+        .collect();
+
+    if opts.weight.is_some() {
+        let mut heatmap = WeightedHeatmap::new(hist_x, hist_y);
+        let files = count_all_files(&opts, &mut heatmap);
+        return write_weighted(opts, heatmap, files, x_edges, y_edges);
+    }
+    let x_min = *x_edges.first().unwrap();
+    let x_max = *x_edges.last().unwrap();
+    let y_min = *y_edges.first().unwrap();
+    let y_max = *y_edges.last().unwrap();
     let mut heatmap = HeatmapU::<HX, HY>::new(hist_x, hist_y);
-    
-    let mut files = Vec::new();
+    let files = count_all_files(&opts, &mut heatmap);
 
-    glob::glob(&opts.files)
-        .unwrap()
-        .filter_map(Result::ok)
-        .for_each(
-            |p| 
-            {
-                let file = File::open(p.as_path())
-                    .expect("cannot open file");
-
-                let ending = p.extension()
-                    .unwrap()
-                    .to_str()
-                    .unwrap();
-                match ending {
-                    "xz" => {
-                        let decoder = LzmaReader::new_decompressor(file).unwrap();
-                        count_into_heatmap(decoder, &mut heatmap, opts.clone())
-                    },
-                    "gz" => {
-                        let decoder = GzDecoder::new(file);
-                        count_into_heatmap(decoder, &mut heatmap, opts.clone())
-                    }
-                    _ => {
-                        count_into_heatmap(file, &mut heatmap, opts.clone())
-                    }
-                }
-                files.push(p);
-            }
-        );
     let mut settings = GnuplotSettings::new();
-    if let Some(x_label) = opts.x_label
+    if let Some(x_label) = opts.x_label.as_ref()
     {
-        settings.x_label(x_label);
+        settings.x_label(x_label.as_str());
     }
-    if let Some(y_label) = opts.y_label
+    if let Some(y_label) = opts.y_label.as_ref()
     {
-        settings.y_label(y_label);
+        settings.y_label(y_label.as_str());
     }
 
     
     settings
         .x_axis(GnuplotAxis::new(x_min, x_max, 5))
         .y_axis(GnuplotAxis::new(y_min, y_max, 5))
-        .palette(opts.palett.into_inner());
+        .palette(opts.palett.clone().into_inner());
     
     println!("creating {}", &opts.gnuplot_name);
     let file = File::create(&opts.gnuplot_name).unwrap();
@@ -117,63 +114,289 @@ pub fn work<X, Y, HX, HY>(
     let misses = heatmap.total_misses();
     let frac = misses as f64 / total as f64;
 
-    writeln!(&mut writer, "#{}", stats::get_cmd_args()).unwrap();
-    writeln!(&mut writer, "#total: {} misses: {} -> frac {}", total, misses, frac).unwrap();
-    files.into_iter()
-        .for_each(
-            |p| 
-            {
-                let realpath = canonicalize(p).unwrap();
-                writeln!(&mut writer, "#{}", realpath.display()).unwrap()
-            }
-        );
+    let filtered = write_header(&mut writer, &opts, total, misses, (&x_edges, &y_edges), files);
+
+    let base = opts.gnuplot_name.trim_end_matches(".gp").to_owned();
+    let mut export = Export::new(base, &opts.export);
+    if !export.is_empty() {
+        let norm = if opts.non_normalized {
+            Normalization::Counts
+        } else {
+            Normalization::Columns
+        };
+        heatmap.export(&mut export, norm, "x", "y");
+        export_info(&mut export, &opts, x_edges, y_edges, total, misses, filtered);
+    }
+    let exported = export.write(&opts.params);
 
     if opts.non_normalized
     {
-        heatmap.gnuplot(writer, opts.gnuplot_output_name, settings)
+        heatmap.gnuplot(writer, &opts.gnuplot_output_name, settings)
             .unwrap();
     } else {
         heatmap.into_heatmap_normalized_columns()
-            .gnuplot(writer, opts.gnuplot_output_name, settings)
+            .gnuplot(writer, &opts.gnuplot_output_name, settings)
             .unwrap();
     }
     
-    
-    println!("fraction of misses, i.e., outside heatmap: {}", frac);
-    println!("Total: {}", total);
+    finish(opts, frac, total, filtered, exported)
+}
 
-    if opts.gnuplot_exec {
-        match Command::new("gnuplot")
-            .arg(opts.gnuplot_name)
-            .output()
+/// Counts the lines of all files into the heatmap and returns the files, see [count_files_parallel]
+fn count_all_files<X, Y, H>(opts: &HeatmapGenericOpts, heatmap: &mut H) -> Vec<PathBuf>
+where H: CountXY<X, Y> + Clone + Send + Sync,
+    X: Display + Copy + 'static,
+    Y: Display + Copy + 'static,
+    f64: AsPrimitive<X> + AsPrimitive<Y>
+{
+    count_files_parallel(
+        &opts.files,
+        (opts.j, opts.no_p_bar),
+        heatmap,
+        |p, heatmap| count_into_heatmap(open_input(p), heatmap, opts.clone()),
+        |heatmap, other| heatmap.combine(other)
+    )
+}
+
+/// Counts each file matching `files` into a clone of the empty `origin` and adds the results to `origin`.
+/// Files are parsed in parallel with `j` threads, with a progress bar unless `no_p_bar`.
+/// Returns the files
+pub(crate) fn count_files_parallel<T, F, C>(
+    files: &str,
+    (j, no_p_bar): (usize, bool),
+    origin: &mut T,
+    count_file: F,
+    combine: C
+) -> Vec<PathBuf>
+where T: Clone + Send + Sync,
+    F: Fn(&Path, &mut T) + Sync,
+    C: Fn(&mut T, &T)
+{
+    let files: Vec<_> = glob::glob(files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(j)
+        .build()
+        .unwrap();
+    let count = |p: &PathBuf|
+    {
+        let mut counted = origin.clone();
+        count_file(p, &mut counted);
+        counted
+    };
+    let results: Vec<T> = pool.install(||
         {
-            Ok(_) => {},
-            Err(error) => {
-                eprintln!("{}", error)
+            if no_p_bar {
+                files.par_iter().map(count).collect()
+            } else {
+                files.par_iter().progress().map(count).collect()
             }
         }
+    );
+    for other in results.iter()
+    {
+        combine(origin, other);
     }
+    files
+}
 
+/// Writes the header of the gnuplot file and returns the number of filtered lines
+fn write_header<W: Write>(
+    mut writer: W,
+    opts: &HeatmapGenericOpts,
+    total: usize,
+    misses: usize,
+    edges: (&[f64], &[f64]),
+    files: Vec<PathBuf>
+) -> u64
+{
+    let frac = misses as f64 / total as f64;
+    let (x_edges, y_edges) = edges;
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    writeln!(writer, "#total: {} misses: {} -> frac {}", total, misses, frac).unwrap();
+    writeln!(writer, "#x: {} y: {}", opts.x, opts.y).unwrap();
+    if let Some(weight) = opts.weight.as_ref() {
+        writeln!(writer, "#weight: {} scale: {}", weight, opts.weight_scale).unwrap();
+    }
+    let filtered = FILTERED_COUNTER.swap(0, Ordering::Relaxed);
+    if let Some(filter) = opts.filter.as_ref() {
+        writeln!(writer, "#where: {} filtered: {}", filter, filtered).unwrap();
+    }
+    writeln!(
+        writer,
+        "#x range: {} {} y range: {} {}",
+        x_edges[0],
+        x_edges[x_edges.len() - 1],
+        y_edges[0],
+        y_edges[y_edges.len() - 1]
+    ).unwrap();
+    files.into_iter()
+        .for_each(
+            |p| 
+            {
+                let realpath = canonicalize(p).unwrap();
+                writeln!(writer, "#{}", realpath.display()).unwrap()
+            }
+        );
+    filtered
 }
 
-pub fn count_into_heatmap<X, Y, Hx, Hy, R>(
-        reader: R,
-        heatmap: &mut HeatmapU<Hx, Hy>,
-        opts: HeatmapGenericOpts
-    )
-where R: Read,
-    X: FromStr + Display + Copy,
-    Y: FromStr + Display + Copy,
-    Hx: HistogramVal<X>,
-    Hy: HistogramVal<Y>
-{
-    let buf_r = BufReader::new(reader);
-    let index_x = opts.x_index;
-    let index_y = opts.y_index;
-    let smaller_index = index_x.min(index_y);
-    let bigger_index = index_x.max(index_y);
-    let dif = bigger_index - smaller_index - 1;
-    buf_r.lines()
+fn export_info(
+    export: &mut Export,
+    opts: &HeatmapGenericOpts,
+    x_edges: Vec<f64>,
+    y_edges: Vec<f64>,
+    total: usize,
+    misses: usize,
+    filtered: u64
+)
+{
+    export.array("x_edges", &["edge"], Array::vector(x_edges))
+        .array("y_edges", &["edge"], Array::vector(y_edges))
+        .info("x", &opts.x)
+        .info("y", &opts.y)
+        .info("total", total)
+        .info("misses", misses);
+    if let Some(label) = opts.x_label.as_ref() {
+        export.info("x_label", label);
+    }
+    if let Some(label) = opts.y_label.as_ref() {
+        export.info("y_label", label);
+    }
+    if let Some(filter) = opts.filter.as_ref() {
+        export.info("where", filter)
+            .info("filtered", filtered);
+    }
+    if let Some(weight) = opts.weight.as_ref() {
+        export.info("weight", weight)
+            .info("weight_scale", opts.weight_scale);
+    }
+}
+
+/// Prints the summary, calls gnuplot if requested and returns the names of the created files
+fn finish(opts: HeatmapGenericOpts, frac: f64, total: usize, filtered: u64, exported: Vec<String>) -> Vec<String>
+{
+    let filtered = opts.filter.as_ref().map(|_| filtered);
+    report_lines("x, y or weight", "heatmap", filtered, frac, total);
+
+    if opts.gnuplot_exec {
+        exec_gnuplot(&[&opts.gnuplot_name]);
+    }
+    let mut outputs = vec![opts.gnuplot_name];
+    outputs.extend(exported);
+    outputs
+}
+
+/// Prints and resets the number of undefined lines (see [UNDEFINED_COUNTER]),
+/// the number of filtered lines, if a filter is used, and the fraction of misses.
+/// `values` names the values that can be undefined, `target` is the heatmap or histogram
+pub(crate) fn report_lines(values: &str, target: &str, filtered: Option<u64>, frac: f64, total: usize)
+{
+    let undefined = UNDEFINED_COUNTER.swap(0, Ordering::Relaxed);
+    if undefined > 0 {
+        println!("skipped {} lines with undefined {}", undefined, values);
+    }
+    if let Some(filtered) = filtered {
+        println!("filtered: {}", filtered);
+    }
+    println!("fraction of misses, i.e., outside {}: {}", target, frac);
+    println!("Total: {}", total);
+}
+
+/// Calls gnuplot for each script, errors are printed
+pub(crate) fn exec_gnuplot<S: AsRef<str>>(scripts: &[S])
+{
+    for script in scripts
+    {
+        if let Err(error) = Command::new("gnuplot").arg(script.as_ref()).output() {
+            eprintln!("{}", error)
+        }
+    }
+}
+
+/// Output of a heatmap with `--weight`: the weights are normalized per column,
+/// or globally with `--non-normalized`, as the raw weights are only known up to a factor
+fn write_weighted<Hx, Hy>(
+    opts: HeatmapGenericOpts,
+    heatmap: WeightedHeatmap<Hx, Hy>,
+    files: Vec<PathBuf>,
+    x_edges: Vec<f64>,
+    y_edges: Vec<f64>
+) -> Vec<String>
+where Hx: Histogram,
+    Hy: Histogram
+{
+    println!("creating {}", &opts.gnuplot_name);
+    let file = File::create(&opts.gnuplot_name).unwrap();
+    let mut writer = BufWriter::new(file);
+    println!("Using gnuplot will generate: {}", &opts.gnuplot_output_name);
+    let total = heatmap.total();
+    let misses = heatmap.total_misses();
+    let frac = misses as f64 / total as f64;
+
+    let filtered = write_header(&mut writer, &opts, total, misses, (&x_edges, &y_edges), files);
+    let global = opts.non_normalized;
+    writeln!(writer, "#normalization: {}", if global { "global" } else { "columns" }).unwrap();
+
+    let base = opts.gnuplot_name.trim_end_matches(".gp").to_owned();
+    let mut export = Export::new(base, &opts.export);
+    if !export.is_empty() {
+        heatmap.export(&mut export, global);
+        export_info(&mut export, &opts, x_edges.clone(), y_edges.clone(), total, misses, filtered);
+    }
+    let exported = export.write(&opts.params);
+
+    heatmap.gnuplot(
+        writer,
+        &opts.gnuplot_output_name,
+        (opts.x_label.as_deref(), opts.y_label.as_deref()),
+        (
+            ValueAxis::from_borders(&x_edges, opts.hist_x.is_integer()),
+            ValueAxis::from_borders(&y_edges, opts.hist_y.is_integer())
+        ),
+        &opts.palett.clone().into_inner(),
+        global
+    ).unwrap();
+
+    finish(opts, frac, total, filtered, exported)
+}
+
+/// Number of lines for which x, y or the weight is NaN, e.g., because a column is missing or not a number,
+/// or for which a value of an integer histogram is not an integer
+pub static UNDEFINED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Number of lines removed by the `--where` filter
+pub static FILTERED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// the first `len` columns of a data line, columns that are not a number are NaN
+pub(crate) fn parse_columns(line: &str, len: usize) -> Vec<f64>
+{
+    line.split_whitespace()
+        .take(len)
+        .map(|v| v.parse::<f64>().unwrap_or(f64::NAN))
+        .collect()
+}
+
+/// false, if the columns do not fulfill the filter. Removed lines are counted
+pub(crate) fn keep_line(filter: Option<&ColumnExpression>, columns: &[f64]) -> bool
+{
+    match filter {
+        Some(filter) if !is_true(filter.eval(columns)) => {
+            FILTERED_COUNTER.fetch_add(1, Ordering::Relaxed);
+            false
+        },
+        _ => true
+    }
+}
+
+/// every `every`th line of the reader, that is neither empty nor a comment
+pub(crate) fn data_lines<R: Read>(reader: R, every: usize) -> impl Iterator<Item=String>
+{
+    BufReader::new(reader)
+        .lines()
         .map(|v| v.unwrap())
         .filter(
             |line|
@@ -182,27 +405,93 @@ where R: Read,
                 !trimmed.starts_with('#') // skip comments
                 && !trimmed.is_empty()
             }
-        ).step_by(opts.every.get())
+        ).step_by(every)
+}
+
+/// x, y and the natural logarithm of the weight of a data line (0 without `--weight`).
+/// None if one of them is undefined (see [HistBuilder::accepts]) or if the line does not fulfill the filter
+fn eval_line(line: &str, opts: &HeatmapGenericOpts, len: usize) -> Option<(f64, f64, f64)>
+{
+    let columns = parse_columns(line, len);
+    if !keep_line(opts.filter.as_ref(), &columns) {
+        return None;
+    }
+    let (x, y) = (&opts.x, &opts.y);
+    let val_x = x.eval(&columns);
+    let val_y = y.eval(&columns);
+    let ln_weight = opts.weight
+        .as_ref()
+        .map_or(0.0, |weight| opts.weight_scale.ln_weight(weight.eval(&columns)));
+    if !opts.hist_x.accepts(val_x) || !opts.hist_y.accepts(val_y) || ln_weight.is_nan() {
+        UNDEFINED_COUNTER.fetch_add(1, Ordering::Relaxed);
+        None
+    } else {
+        Some((val_x, val_y, ln_weight))
+    }
+}
+
+/// number of columns that have to be parsed for x, y, the filter and the weight
+fn columns_needed(opts: &HeatmapGenericOpts) -> usize
+{
+    opts.x.max_column()
+        .max(opts.y.max_column())
+        .max(opts.filter.as_ref().and_then(ColumnExpression::max_column))
+        .max(opts.weight.as_ref().and_then(ColumnExpression::max_column))
+        .map_or(0, |max| max + 1)
+}
+
+/// Values of x and y of all files, used to determine auto ranges.
+/// Lines with undefined values are skipped
+fn collect_columns(opts: &HeatmapGenericOpts) -> (Vec<f64>, Vec<f64>)
+{
+    let mut x_values = Vec::new();
+    let mut y_values = Vec::new();
+    let len = columns_needed(opts);
+    glob::glob(&opts.files)
+        .unwrap()
+        .filter_map(Result::ok)
         .for_each(
-            |line|
+            |p|
             {
-                let slice = line.trim_start();
-                let mut it = slice.split_whitespace();
-
-                let smaller = it.nth(smaller_index).unwrap();
-                let bigger = it.nth(dif).unwrap();
-                let (val_x, val_y) = if index_x < index_y {
-                    (
-                        smaller.parse::<X>().ok().unwrap(),
-                        bigger.parse::<Y>().ok().unwrap()
-                    )
-                } else {
-                    (
-                        bigger.parse::<X>().ok().unwrap(),
-                        smaller.parse::<Y>().ok().unwrap()
+                data_lines(open_input(p.as_path()), opts.every.get())
+                    .filter_map(|line| eval_line(&line, opts, len))
+                    .for_each(
+                        |(x, y, _)|
+                        {
+                            x_values.push(x);
+                            y_values.push(y);
+                        }
                     )
-                };
-                match heatmap.count(val_x, val_y)
+            }
+        );
+    // undefined and filtered lines are reported by the counting pass
+    UNDEFINED_COUNTER.store(0, Ordering::Relaxed);
+    FILTERED_COUNTER.store(0, Ordering::Relaxed);
+    (x_values, y_values)
+}
+
+/// Counts x and y of each line into the heatmap, weighted if `--weight` is used.
+/// Values are converted to the type of the histograms, lines with non-integer values for integer histograms are undefined
+pub fn count_into_heatmap<X, Y, H, R>(
+        reader: R,
+        heatmap: &mut H,
+        opts: HeatmapGenericOpts
+    )
+where R: Read,
+    X: Display + Copy + 'static,
+    Y: Display + Copy + 'static,
+    f64: AsPrimitive<X> + AsPrimitive<Y>,
+    H: CountXY<X, Y>
+{
+    let len = columns_needed(&opts);
+    data_lines(reader, opts.every.get())
+        .filter_map(|line| eval_line(&line, &opts, len))
+        .for_each(
+            |(x, y, ln_weight)|
+            {
+                let val_x: X = x.as_();
+                let val_y: Y = y.as_();
+                match heatmap.count_xy(val_x, val_y, ln_weight)
                 {
                     Ok(..) => {},
                     Err(error) => {
@@ -213,4 +502,4 @@ where R: Read,
                 }
             }
         )
-}
\ No newline at end of file
+}
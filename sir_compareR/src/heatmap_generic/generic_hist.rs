@@ -0,0 +1,236 @@
+use crate::heatmap_generic::*;
+use sampling::*;
+use std::{fmt::Display, fs::*, io::{BufWriter, Write}};
+use std::path::PathBuf;
+use std::sync::atomic::*;
+use crate::input::open_input;
+use num_traits::AsPrimitive;
+use crate::stats;
+use crate::export::{Export, Array};
+use crate::expression::ColumnExpression;
+use crate::heatmap2::ValueAxis;
+
+/// Histogram of one column (or expression over columns) of all files.
+/// Writes the counts, probabilities, log10 probabilities and the cumulative distribution
+/// as data file together with a gnuplot script
+pub fn generate_histogram(mut opts: HistogramGenericOpts) -> Result<Vec<String>, String>
+{
+    if opts.hist.is_auto() {
+        let values = collect_values(&opts);
+        opts.hist = opts.hist.resolve("x", values)?;
+    }
+    let hist = opts.hist.build()
+        .expect("Error during histogram build!");
+
+    let outputs = match hist {
+        HistWrapper::Isize{hist} => work_hist(opts, hist, true),
+        HistWrapper::F64{hist} => work_hist(opts, hist, false)
+    };
+    Ok(outputs)
+}
+
+/// number of columns that have to be parsed for the value and the filter
+fn columns_needed(opts: &HistogramGenericOpts) -> usize
+{
+    opts.x.max_column()
+        .max(opts.filter.as_ref().and_then(ColumnExpression::max_column))
+        .map_or(0, |max| max + 1)
+}
+
+/// value of a data line, None if it is undefined (see [HistBuilder::accepts]) or if the line does not fulfill the filter
+fn eval_value(line: &str, opts: &HistogramGenericOpts, len: usize) -> Option<f64>
+{
+    let columns = parse_columns(line, len);
+    if !keep_line(opts.filter.as_ref(), &columns) {
+        return None;
+    }
+    let val = opts.x.eval(&columns);
+    if !opts.hist.accepts(val) {
+        UNDEFINED_COUNTER.fetch_add(1, Ordering::Relaxed);
+        None
+    } else {
+        Some(val)
+    }
+}
+
+/// Values of all files, used to determine an auto range
+fn collect_values(opts: &HistogramGenericOpts) -> Vec<f64>
+{
+    let len = columns_needed(opts);
+    let values = glob::glob(&opts.files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .flat_map(
+            |p|
+            {
+                data_lines(open_input(p.as_path()), opts.every.get())
+                    .filter_map(|line| eval_value(&line, opts, len))
+                    .collect::<Vec<_>>()
+            }
+        ).collect();
+    // undefined and filtered lines are reported by the counting pass
+    UNDEFINED_COUNTER.store(0, Ordering::Relaxed);
+    FILTERED_COUNTER.store(0, Ordering::Relaxed);
+    values
+}
+
+/// Distribution derived from the counts of a histogram
+struct Distribution{
+    probability: Vec<f64>,
+    log10_probability: Vec<f64>,
+    cumulative: Vec<f64>
+}
+
+impl Distribution{
+    fn new(counts: &[usize]) -> Self
+    {
+        let inside: usize = counts.iter().sum();
+        let probability: Vec<f64> = counts.iter()
+            .map(|&count| if inside > 0 { count as f64 / inside as f64 } else { 0.0 })
+            .collect();
+        let log10_probability = probability.iter()
+            .map(|&p| if p > 0.0 { p.log10() } else { f64::NAN })
+            .collect();
+        let mut sum = 0.0;
+        let cumulative = probability.iter()
+            .map(|&p| {
+                sum += p;
+                sum
+            }).collect();
+        Self{
+            probability,
+            log10_probability,
+            cumulative
+        }
+    }
+}
+
+fn work_hist<X, H>(opts: HistogramGenericOpts, mut hist: H, integer: bool) -> Vec<String>
+where H: HistogramVal<X>,
+    X: AsPrimitive<f64> + Display,
+    f64: AsPrimitive<X>
+{
+    let edges: Vec<f64> = hist.borders_clone()
+        .unwrap()
+        .into_iter()
+        .map(|border| border.as_())
+        .collect();
+
+    let len = columns_needed(&opts);
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut misses = 0_usize;
+    glob::glob(&opts.files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .for_each(
+            |p|
+            {
+                data_lines(open_input(p.as_path()), opts.every.get())
+                    .filter_map(|line| eval_value(&line, &opts, len))
+                    .for_each(
+                        |val|
+                        {
+                            let val: X = val.as_();
+                            if let Err(error) = hist.count_val(val) {
+                                misses += 1;
+                                if !opts.supress_hist_error {
+                                    println!("{:?} x: {}", error, val);
+                                }
+                            }
+                        }
+                    );
+                files.push(p);
+            }
+        );
+
+    let counts = hist.hist().clone();
+    let distribution = Distribution::new(&counts);
+    let inside: usize = counts.iter().sum();
+    let total = inside + misses;
+    let frac = misses as f64 / total as f64;
+    let filtered = FILTERED_COUNTER.swap(0, Ordering::Relaxed);
+
+    println!("creating {}", &opts.data_name);
+    let file = File::create(&opts.data_name).unwrap();
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    writeln!(writer, "#total: {} misses: {} -> frac {}", total, misses, frac).unwrap();
+    writeln!(writer, "#x: {}", opts.x).unwrap();
+    if let Some(filter) = opts.filter.as_ref() {
+        writeln!(writer, "#where: {} filtered: {}", filter, filtered).unwrap();
+    }
+    files.iter()
+        .for_each(
+            |p|
+            {
+                let realpath = canonicalize(p).unwrap();
+                writeln!(writer, "#{}", realpath.display()).unwrap()
+            }
+        );
+    writeln!(writer, "#left right center count p log10_p cumulative").unwrap();
+    let centers = ValueAxis::from_borders(&edges, integer).centers();
+    for (i, count) in counts.iter().enumerate()
+    {
+        let (left, right) = (edges[i], edges[i + 1]);
+        writeln!(
+            writer,
+            "{} {} {} {} {:e} {} {:e}",
+            left,
+            right,
+            centers[i],
+            count,
+            distribution.probability[i],
+            distribution.log10_probability[i],
+            distribution.cumulative[i]
+        ).unwrap();
+    }
+    drop(writer);
+
+    println!("creating {}", &opts.gnuplot_name);
+    let file = File::create(&opts.gnuplot_name).unwrap();
+    let mut writer = BufWriter::new(file);
+    println!("Using gnuplot will generate: {}", &opts.gnuplot_output_name);
+    writeln!(writer, "#{}", stats::get_cmd_args()).unwrap();
+    writeln!(writer, "set t pdfcairo").unwrap();
+    writeln!(writer, "set output \"{}\"", opts.gnuplot_output_name).unwrap();
+    writeln!(writer, "set xlabel \"{}\"", opts.x_label.as_deref().unwrap_or("x")).unwrap();
+    writeln!(writer, "set ylabel \"P\"").unwrap();
+    writeln!(writer, "set style fill solid 0.5").unwrap();
+    writeln!(writer, "plot \"{}\" u 3:5:($2-$1) with boxes t \"\"", opts.data_name).unwrap();
+    writeln!(writer, "set ylabel \"cumulative\"").unwrap();
+    writeln!(writer, "plot \"{}\" u 2:7 with steps t \"\"", opts.data_name).unwrap();
+    writeln!(writer, "set output").unwrap();
+    drop(writer);
+
+    let base = opts.gnuplot_name.trim_end_matches(".gp").to_owned();
+    let mut export = Export::new(base, &opts.export);
+    if !export.is_empty() {
+        let counts: Vec<u64> = counts.iter().map(|&count| count as u64).collect();
+        export.array("counts", &["x"], Array::vector(counts))
+            .array("probability", &["x"], Array::vector(distribution.probability))
+            .array("log10_probability", &["x"], Array::vector(distribution.log10_probability))
+            .array("cumulative", &["x"], Array::vector(distribution.cumulative))
+            .array("x_edges", &["edge"], Array::vector(edges))
+            .info("x", &opts.x)
+            .info("total", total)
+            .info("misses", misses);
+        if let Some(label) = opts.x_label.as_ref() {
+            export.info("x_label", label);
+        }
+        if let Some(filter) = opts.filter.as_ref() {
+            export.info("where", filter)
+                .info("filtered", filtered);
+        }
+    }
+    let exported = export.write(&opts.params);
+
+    let filtered = opts.filter.as_ref().map(|_| filtered);
+    report_lines("x", "histogram", filtered, frac, total);
+
+    if opts.gnuplot_exec {
+        exec_gnuplot(&[&opts.gnuplot_name]);
+    }
+    let mut outputs = vec![opts.data_name, opts.gnuplot_name];
+    outputs.extend(exported);
+    outputs
+}
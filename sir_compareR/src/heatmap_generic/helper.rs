@@ -1,14 +1,25 @@
-use std::{convert::TryFrom, str::FromStr, num::*};
+use std::{convert::TryFrom, str::FromStr, num::*, iter::once};
 use crate::parse_cmd::{GnuPalett, Opt};
 use sampling::*;
+use crate::auto_range::AutoRange;
+use crate::export::ExportFormat;
+use crate::expression::ColumnExpression;
+use crate::heatmap_generic::WeightScale;
+use crate::header::{read_header_lines, resolve_params, HeaderParams};
+use crate::input::open_input;
 
 #[derive(Debug, Clone)]
 pub struct HeatmapGenericOpts
 {
     pub hist_x: HistBuilder,
     pub hist_y: HistBuilder,
-    pub x_index: usize,
-    pub y_index: usize,
+    pub x: ColumnExpression,
+    pub y: ColumnExpression,
+    pub filter: Option<ColumnExpression>,
+    pub weight: Option<ColumnExpression>,
+    pub weight_scale: WeightScale,
+    pub j: usize,
+    pub no_p_bar: bool,
     pub files: String,
     pub every: NonZeroUsize,
     pub x_label: Option<String>,
@@ -18,12 +29,15 @@ pub struct HeatmapGenericOpts
     pub gnuplot_output_name: String,
     pub supress_hist_error: bool,
     pub gnuplot_exec: bool,
-    pub palett: GnuPalett
+    pub palett: GnuPalett,
+    pub export: Vec<ExportFormat>,
+    /// parameters from the file headers, used for the export
+    pub params: HeaderParams
 }
 
 impl TryFrom<Opt> for HeatmapGenericOpts
 {
-    type Error = &'static str;
+    type Error = String;
     fn try_from(opt: Opt) -> Result<Self, Self::Error> 
     {
         match opt
@@ -31,8 +45,13 @@ impl TryFrom<Opt> for HeatmapGenericOpts
             Opt::GenericHeatmap{
                 hist_x,
                 hist_y,
-                x_index,
-                y_index,
+                mut x,
+                mut y,
+                mut filter,
+                mut weight,
+                weight_scale,
+                j,
+                no_p_bar,
                 files,
                 every,
                 x_label,
@@ -42,52 +61,241 @@ impl TryFrom<Opt> for HeatmapGenericOpts
                 gnuplot_output_name,
                 supress_hist_error,
                 gnuplot,
-                palett
+                palett,
+                export
             } => {
-                if x_index == y_index {
-                    Err("Indizes are not allowed to be identical")
-                } else {
-                    let output = match gnuplot_output_name
-                    {
-                        Some(name) => name,
-                        None => {
-                            let mut name = name.as_str();
-                            if name.ends_with(".gp")
-                            {
-                                name = &name[..name.len()-3];
-                            }
-                            format!("{}.pdf", name)
+                let exprs = once(&mut x)
+                    .chain(once(&mut y))
+                    .chain(filter.as_mut())
+                    .chain(weight.as_mut());
+                resolve_column_names(&files, exprs)?;
+                let params = resolve_params(&files)?;
+                let output = match gnuplot_output_name
+                {
+                    Some(name) => name,
+                    None => {
+                        let mut name = name.as_str();
+                        if name.ends_with(".gp")
+                        {
+                            name = &name[..name.len()-3];
                         }
-                    };
-                    if !name.ends_with(".gp")
-                    {
-                        name = format!("{}.gp", name);
+                        format!("{}.pdf", name)
                     }
-                    Ok(
-                        Self{
-                            hist_x,
-                            hist_y,
-                            y_index,
-                            x_index,
-                            files,
-                            every,
-                            y_label,
-                            x_label,
-                            non_normalized,
-                            gnuplot_name: name,
-                            gnuplot_output_name: output,
-                            supress_hist_error,
-                            gnuplot_exec: gnuplot,
-                            palett,
-                        }
-                    )
-                }  
+                };
+                if !name.ends_with(".gp")
+                {
+                    name = format!("{}.gp", name);
+                }
+                Ok(
+                    Self{
+                        hist_x,
+                        hist_y,
+                        y,
+                        x,
+                        filter,
+                        weight,
+                        weight_scale,
+                        j,
+                        no_p_bar,
+                        files,
+                        every,
+                        y_label,
+                        x_label,
+                        non_normalized,
+                        gnuplot_name: name,
+                        gnuplot_output_name: output,
+                        supress_hist_error,
+                        gnuplot_exec: gnuplot,
+                        palett,
+                        export,
+                        params
+                    }
+                )
+            },
+            _ => Err("wrong arm".to_owned())
+        }    
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramGenericOpts
+{
+    pub hist: HistBuilder,
+    pub x: ColumnExpression,
+    pub filter: Option<ColumnExpression>,
+    pub files: String,
+    pub every: NonZeroUsize,
+    pub x_label: Option<String>,
+    pub data_name: String,
+    pub gnuplot_name: String,
+    pub gnuplot_output_name: String,
+    pub supress_hist_error: bool,
+    pub gnuplot_exec: bool,
+    pub export: Vec<ExportFormat>,
+    /// parameters from the file headers, used for the export
+    pub params: HeaderParams
+}
+
+impl TryFrom<Opt> for HistogramGenericOpts
+{
+    type Error = String;
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> 
+    {
+        match opt
+        {
+            Opt::GenericHistogram{
+                hist,
+                mut x,
+                mut filter,
+                files,
+                every,
+                x_label,
+                name,
+                gnuplot_output_name,
+                supress_hist_error,
+                gnuplot,
+                export
+            } => {
+                resolve_column_names(&files, once(&mut x).chain(filter.as_mut()))?;
+                let params = resolve_params(&files)?;
+                let base = name.trim_end_matches(".gp");
+                let output = gnuplot_output_name
+                    .unwrap_or_else(|| format!("{}.pdf", base));
+                Ok(
+                    Self{
+                        hist,
+                        x,
+                        filter,
+                        files,
+                        every,
+                        x_label,
+                        data_name: format!("{}.dat", base),
+                        gnuplot_name: format!("{}.gp", base),
+                        gnuplot_output_name: output,
+                        supress_hist_error,
+                        gnuplot_exec: gnuplot,
+                        export,
+                        params
+                    }
+                )
+            },
+            _ => Err("wrong arm".to_owned())
+        }    
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram3dOpts
+{
+    /// histograms of the x, y and z axis
+    pub hists: [HistBuilder; 3],
+    pub values: [ColumnExpression; 3],
+    pub labels: [Option<String>; 3],
+    pub filter: Option<ColumnExpression>,
+    pub files: String,
+    pub every: NonZeroUsize,
+    pub base_name: String,
+    pub gnuplot_exec: bool,
+    pub palett: GnuPalett,
+    pub j: usize,
+    pub no_p_bar: bool,
+    pub export: Vec<ExportFormat>,
+    /// parameters from the file headers, used for the export
+    pub params: HeaderParams
+}
+
+impl TryFrom<Opt> for Histogram3dOpts
+{
+    type Error = String;
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> 
+    {
+        match opt
+        {
+            Opt::Histogram3d{
+                hist_x,
+                hist_y,
+                hist_z,
+                x,
+                y,
+                z,
+                mut filter,
+                files,
+                every,
+                x_label,
+                y_label,
+                z_label,
+                name,
+                gnuplot,
+                palett,
+                j,
+                no_p_bar,
+                export
+            } => {
+                let mut values = [x, y, z];
+                resolve_column_names(&files, values.iter_mut().chain(filter.as_mut()))?;
+                let params = resolve_params(&files)?;
+                Ok(
+                    Self{
+                        hists: [hist_x, hist_y, hist_z],
+                        values,
+                        labels: [x_label, y_label, z_label],
+                        filter,
+                        files,
+                        every,
+                        base_name: name.trim_end_matches(".gp").to_owned(),
+                        gnuplot_exec: gnuplot,
+                        palett,
+                        j,
+                        no_p_bar,
+                        export,
+                        params
+                    }
+                )
             },
-            _ => Err("wrong arm")
+            _ => Err("wrong arm".to_owned())
         }    
     }
 }
 
+/// Resolves the column names used in the expressions with the names from the header, see [column_names].
+/// The header is only read, if one of the expressions uses names
+fn resolve_column_names<'a, I>(files: &str, exprs: I) -> Result<(), String>
+where I: Iterator<Item=&'a mut ColumnExpression>
+{
+    let mut exprs: Vec<_> = exprs.collect();
+    if !exprs.iter().any(|expr| expr.has_names()) {
+        return Ok(());
+    }
+    let names = column_names(files)?;
+    for expr in exprs.iter_mut()
+    {
+        expr.resolve_names(&names)
+            .map_err(|error| format!("{}. Columns in header: {:?}", error, names))?;
+    }
+    Ok(())
+}
+
+/// Column names from the last `#` header line of the first file.
+/// Error, if no file matches
+pub fn column_names(files: &str) -> Result<Vec<String>, String>
+{
+    let first = glob::glob(files)
+        .map_err(|error| format!("invalid file pattern {}: {}", files, error))?
+        .find_map(Result::ok)
+        .ok_or_else(|| format!("no file matches {}", files))?;
+    let names = read_header_lines(open_input(first))
+        .last()
+        .map(|line| {
+            line.trim_start_matches('#')
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_lowercase())
+                .collect()
+        }).unwrap_or_default();
+    Ok(names)
+}
+
+/// Histogram for one axis of the generic heatmap
 #[derive(Debug, Clone)]
 pub enum HistBuilder{
     F64Hist {
@@ -99,7 +307,11 @@ pub enum HistBuilder{
         bins: usize,
         left: isize,
         right: isize,
-    }
+    },
+    /// float histogram with range determined from the data, see [HistBuilder::resolve]
+    F64Auto(AutoRange),
+    /// integer histogram with range determined from the data, see [HistBuilder::resolve]
+    IsizeAuto(AutoRange)
 }
 
 #[derive(Debug, Clone)]
@@ -108,8 +320,55 @@ pub enum HistWrapper{
     F64 {hist: HistF64}
 }
 
+impl HistWrapper{
+    /// bin index of `val`, which has to be an integer for integer histograms, see [HistBuilder::accepts]
+    pub fn bin_index(&self, val: f64) -> Result<usize, HistErrors>
+    {
+        match self {
+            HistWrapper::Isize{hist} => hist.get_bin_index(val as isize),
+            HistWrapper::F64{hist} => hist.get_bin_index(val)
+        }
+    }
+
+    pub fn bin_count(&self) -> usize
+    {
+        match self {
+            HistWrapper::Isize{hist} => hist.bin_count(),
+            HistWrapper::F64{hist} => hist.bin_count()
+        }
+    }
+
+    /// borders of the bins
+    pub fn edges(&self) -> Vec<f64>
+    {
+        match self {
+            HistWrapper::Isize{hist} => hist.borders()
+                .iter()
+                .map(|&border| border as f64)
+                .collect(),
+            HistWrapper::F64{hist} => hist.borders().clone()
+        }
+    }
+}
+
 impl HistBuilder 
 {
+    /// false for NaN and for values that are not integers, if the histogram is an integer histogram.
+    /// Such values are undefined and are not counted
+    pub fn accepts(&self, val: f64) -> bool
+    {
+        if self.is_integer() {
+            val.fract() == 0.0
+        } else {
+            !val.is_nan()
+        }
+    }
+
+    /// true for integer histograms, their right borders are exclusive
+    pub fn is_integer(&self) -> bool
+    {
+        matches!(self, Self::IsizeHist{..} | Self::IsizeAuto(_))
+    }
 
     pub fn build(&self) -> Option<HistWrapper>
     {
@@ -127,7 +386,41 @@ impl HistBuilder
                         hist: HistIsize::new(*left, *right, *bins).ok()?
                     }
                 )
-            }
+            },
+            HistBuilder::F64Auto(_) | HistBuilder::IsizeAuto(_) => None
+        }
+    }
+
+    /// Range is determined from the data, see [HistBuilder::resolve]
+    pub fn is_auto(&self) -> bool
+    {
+        matches!(self, HistBuilder::F64Auto(_) | HistBuilder::IsizeAuto(_))
+    }
+
+    /// Replaces an auto range by the range of the `values` and reports it.
+    /// Fixed ranges are returned unchanged
+    pub fn resolve(&self, axis: &str, values: Vec<f64>) -> Result<Self, String>
+    {
+        match self {
+            HistBuilder::F64Auto(range) => {
+                let (left, right) = range.f64_range(values)?;
+                range.report(axis, left, right);
+                Ok(HistBuilder::F64Hist{
+                    bins: range.bins,
+                    left,
+                    right
+                })
+            },
+            HistBuilder::IsizeAuto(range) => {
+                let (left, right) = range.int_range(values)?;
+                range.report(axis, left, right);
+                Ok(HistBuilder::IsizeHist{
+                    bins: range.bins,
+                    left: left as isize,
+                    right: right as isize
+                })
+            },
+            _ => Ok(self.clone())
         }
     }
 }
@@ -142,6 +435,17 @@ impl FromStr for HistBuilder {
         let bins = iter.next().expect("Not enough arguments, bins missing");
         let bins = bins.parse::<usize>().unwrap();
         let left = iter.next().expect("Not enough arguments, left missing");
+
+        if left == "auto" {
+            let range = AutoRange::parse(bins, iter)?;
+            return if lower.starts_with('i') {
+                Ok(Self::IsizeAuto(range))
+            } else if lower.starts_with('f') {
+                Ok(Self::F64Auto(range))
+            } else {
+                Err("Usage: 'f bins auto [clip]'. Choose either i for integer or f for floats. E.g. 'i 100 auto 0.01'")
+            };
+        }
         let right = iter.next().expect("Not enough arguments, right missing");
 
         assert_eq!(iter.next(), None, "HeatmapBuilder: To many arguments");
@@ -0,0 +1,376 @@
+use crate::heatmap_generic::*;
+use std::{fs::*, io::{BufWriter, Write}};
+use std::path::PathBuf;
+use std::sync::atomic::*;
+use crate::input::open_input;
+use crate::stats;
+use crate::export::{Export, Array};
+use crate::expression::ColumnExpression;
+use crate::heatmap2::ValueAxis;
+use sampling::HistErrors;
+
+const AXES: [&str; 3] = ["x", "y", "z"];
+
+/// Joint histogram of three values
+#[derive(Debug, Clone)]
+pub struct Histogram3d{
+    hists: [HistWrapper; 3],
+    shape: [usize; 3],
+    // C order, i.e., index (x * ny + y) * nz + z
+    counts: Vec<usize>,
+    total: usize,
+    misses: usize
+}
+
+impl Histogram3d{
+    pub fn new(hists: [HistWrapper; 3]) -> Self
+    {
+        let shape = [hists[0].bin_count(), hists[1].bin_count(), hists[2].bin_count()];
+        Self{
+            hists,
+            shape,
+            counts: vec![0; shape.iter().product()],
+            total: 0,
+            misses: 0
+        }
+    }
+
+    /// number of bins of each axis
+    pub fn shape(&self) -> [usize; 3]
+    {
+        self.shape
+    }
+
+    /// number of counted points, including misses
+    pub fn total(&self) -> usize
+    {
+        self.total
+    }
+
+    /// number of points outside the histogram
+    pub fn total_misses(&self) -> usize
+    {
+        self.misses
+    }
+
+    /// borders of the bins of `axis`
+    pub fn edges(&self, axis: usize) -> Vec<f64>
+    {
+        self.hists[axis].edges()
+    }
+
+    fn index(&self, bins: [usize; 3]) -> usize
+    {
+        (bins[0] * self.shape[1] + bins[1]) * self.shape[2] + bins[2]
+    }
+
+    pub fn get(&self, bins: [usize; 3]) -> usize
+    {
+        self.counts[self.index(bins)]
+    }
+
+    /// Counts the point. On a miss, the axis and error of the first value outside the histogram is returned
+    pub fn count(&mut self, vals: [f64; 3]) -> Result<(), (usize, HistErrors)>
+    {
+        self.total += 1;
+        let mut bins = [0; 3];
+        for axis in 0..3
+        {
+            match self.hists[axis].bin_index(vals[axis]) {
+                Ok(bin) => bins[axis] = bin,
+                Err(error) => {
+                    self.misses += 1;
+                    return Err((axis, error));
+                }
+            }
+        }
+        let index = self.index(bins);
+        self.counts[index] += 1;
+        Ok(())
+    }
+
+    /// Adds the counts of `other`, which has to have the same histograms
+    pub fn combine(&mut self, other: &Self)
+    {
+        assert_eq!(self.shape, other.shape, "Histogram3d: dimensions do not match");
+        self.counts.iter_mut()
+            .zip(other.counts.iter())
+            .for_each(|(this, other)| *this += other);
+        self.total += other.total;
+        self.misses += other.misses;
+    }
+
+    /// Counts summed over the remaining axis, row major with `col_axis` as columns and `row_axis` as rows
+    pub fn marginal(&self, col_axis: usize, row_axis: usize) -> Vec<usize>
+    {
+        let mut marginal = vec![0; self.shape[col_axis] * self.shape[row_axis]];
+        let width = self.shape[col_axis];
+        for x in 0..self.shape[0] {
+            for y in 0..self.shape[1] {
+                for z in 0..self.shape[2] {
+                    let bins = [x, y, z];
+                    marginal[bins[row_axis] * width + bins[col_axis]] += self.get(bins);
+                }
+            }
+        }
+        marginal
+    }
+
+    /// Counts of the z bin, row major with x as columns and y as rows
+    pub fn z_slice(&self, z: usize) -> Vec<usize>
+    {
+        let mut slice = Vec::with_capacity(self.shape[0] * self.shape[1]);
+        for y in 0..self.shape[1] {
+            slice.extend((0..self.shape[0]).map(|x| self.get([x, y, z])));
+        }
+        slice
+    }
+}
+
+/// Entries divided by their sum, all 0 if the sum is 0
+fn probabilities(counts: &[usize]) -> Vec<f64>
+{
+    let sum: usize = counts.iter().sum();
+    counts.iter()
+        .map(|&count| if sum > 0 { count as f64 / sum as f64 } else { 0.0 })
+        .collect()
+}
+
+/// number of columns that have to be parsed for the values and the filter
+fn columns_needed(opts: &Histogram3dOpts) -> usize
+{
+    opts.values
+        .iter()
+        .chain(opts.filter.iter())
+        .filter_map(ColumnExpression::max_column)
+        .max()
+        .map_or(0, |max| max + 1)
+}
+
+/// x, y and z of a data line, None if one of them is undefined (see [HistBuilder::accepts])
+/// or if the line does not fulfill the filter
+fn eval_values(line: &str, opts: &Histogram3dOpts, len: usize) -> Option<[f64; 3]>
+{
+    let columns = parse_columns(line, len);
+    if !keep_line(opts.filter.as_ref(), &columns) {
+        return None;
+    }
+    let vals = [
+        opts.values[0].eval(&columns),
+        opts.values[1].eval(&columns),
+        opts.values[2].eval(&columns)
+    ];
+    let undefined = opts.hists.iter()
+        .zip(vals.iter())
+        .any(|(hist, &val)| !hist.accepts(val));
+    if undefined {
+        UNDEFINED_COUNTER.fetch_add(1, Ordering::Relaxed);
+        None
+    } else {
+        Some(vals)
+    }
+}
+
+fn glob_files(opts: &Histogram3dOpts) -> Vec<PathBuf>
+{
+    glob::glob(&opts.files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+/// Values of all files for each axis, used to determine auto ranges
+fn collect_values(opts: &Histogram3dOpts) -> Vec<Vec<f64>>
+{
+    let len = columns_needed(opts);
+    let mut values = vec![Vec::new(); 3];
+    for p in glob_files(opts)
+    {
+        data_lines(open_input(p.as_path()), opts.every.get())
+            .filter_map(|line| eval_values(&line, opts, len))
+            .for_each(|vals| {
+                for (axis, &val) in vals.iter().enumerate() {
+                    values[axis].push(val);
+                }
+            });
+    }
+    // undefined and filtered lines are reported by the counting pass
+    UNDEFINED_COUNTER.store(0, Ordering::Relaxed);
+    FILTERED_COUNTER.store(0, Ordering::Relaxed);
+    values
+}
+
+/// Counts the lines of all files and returns the files, see [count_files_parallel]
+fn count_all_files(opts: &Histogram3dOpts, hist: &mut Histogram3d) -> Vec<PathBuf>
+{
+    let len = columns_needed(opts);
+    count_files_parallel(
+        &opts.files,
+        (opts.j, opts.no_p_bar),
+        hist,
+        |p, hist| {
+            data_lines(open_input(p), opts.every.get())
+                .filter_map(|line| eval_values(&line, opts, len))
+                .for_each(|vals| {
+                    // misses are counted by the histogram
+                    let _ = hist.count(vals);
+                });
+        },
+        Histogram3d::combine
+    )
+}
+
+/// Bins three values of each line into a [Histogram3d].
+/// Writes the full grid, gnuplot scripts of the three 2D marginals
+/// and of the x-y heatmap for each z bin
+pub fn generate_histogram_3d(mut opts: Histogram3dOpts) -> Result<Vec<String>, String>
+{
+    if opts.hists.iter().any(HistBuilder::is_auto) {
+        let values = collect_values(&opts);
+        for (axis, values) in values.into_iter().enumerate()
+        {
+            opts.hists[axis] = opts.hists[axis].resolve(AXES[axis], values)?;
+        }
+    }
+    let build = |axis: usize| opts.hists[axis]
+        .build()
+        .expect("Error during histogram build!");
+    let mut hist = Histogram3d::new([build(0), build(1), build(2)]);
+    let files = count_all_files(&opts, &mut hist);
+
+    let total = hist.total();
+    let misses = hist.total_misses();
+    let frac = misses as f64 / total as f64;
+    let filtered = FILTERED_COUNTER.swap(0, Ordering::Relaxed);
+    let edges: Vec<Vec<f64>> = (0..3).map(|axis| hist.edges(axis)).collect();
+    let axis = |axis: usize| ValueAxis::from_borders(&edges[axis], opts.hists[axis].is_integer());
+
+    let mut header = vec![
+        stats::get_cmd_args(),
+        format!("total: {} misses: {} -> frac {}", total, misses, frac),
+        format!("x: {} y: {} z: {}", opts.values[0], opts.values[1], opts.values[2])
+    ];
+    if let Some(filter) = opts.filter.as_ref() {
+        header.push(format!("where: {} filtered: {}", filter, filtered));
+    }
+    header.extend(
+        files.iter()
+            .map(|p| canonicalize(p).unwrap().display().to_string())
+    );
+
+    let grid_name = format!("{}.grid.dat", opts.base_name);
+    println!("creating {}", grid_name);
+    let mut writer = BufWriter::new(File::create(&grid_name).unwrap());
+    for line in header.iter()
+    {
+        writeln!(writer, "#{}", line).unwrap();
+    }
+    writeln!(writer, "#ix iy iz x y z count p").unwrap();
+    let (x_centers, y_centers, z_centers) = (axis(0).centers(), axis(1).centers(), axis(2).centers());
+    let shape = hist.shape();
+    let inside = (total - misses) as f64;
+    for (x, x_center) in x_centers.iter().enumerate() {
+        for (y, y_center) in y_centers.iter().enumerate() {
+            for (z, z_center) in z_centers.iter().enumerate() {
+                let count = hist.get([x, y, z]);
+                let p = if inside > 0.0 { count as f64 / inside } else { 0.0 };
+                writeln!(
+                    writer,
+                    "{} {} {} {} {} {} {} {:e}",
+                    x, y, z,
+                    x_center, y_center, z_center,
+                    count,
+                    p
+                ).unwrap();
+            }
+        }
+    }
+    drop(writer);
+    let mut outputs = vec![grid_name];
+
+    let palette = opts.palett.clone().into_inner();
+    let label = |axis: usize| opts.labels[axis]
+        .clone()
+        .unwrap_or_else(|| AXES[axis].to_owned());
+    let mut write_script = |suffix: &str, pages: Vec<MatrixPage>, col_axis: usize, row_axis: usize|
+    {
+        let name = format!("{}_{}.gp", opts.base_name, suffix);
+        println!("creating {}", name);
+        let mut writer = BufWriter::new(File::create(&name).unwrap());
+        for line in header.iter()
+        {
+            writeln!(writer, "#{}", line).unwrap();
+        }
+        write_matrix_gnuplot(
+            writer,
+            &format!("{}_{}.pdf", opts.base_name, suffix),
+            &pages,
+            (shape[col_axis], shape[row_axis]),
+            (Some(&label(col_axis)), Some(&label(row_axis))),
+            (axis(col_axis), axis(row_axis)),
+            &palette
+        ).unwrap();
+        outputs.push(name);
+    };
+
+    // marginals are joint probabilities of two values
+    for &(col_axis, row_axis) in [(0, 1), (0, 2), (1, 2)].iter()
+    {
+        let page = MatrixPage{
+            title: format!("P({}, {})", AXES[col_axis], AXES[row_axis]),
+            values: probabilities(&hist.marginal(col_axis, row_axis))
+        };
+        let suffix = format!("{}{}", AXES[col_axis], AXES[row_axis]);
+        write_script(&suffix, vec![page], col_axis, row_axis);
+    }
+    // slices are conditional probabilities P(x, y | z)
+    let pages = (0..shape[2])
+        .map(|z| MatrixPage{
+            title: format!("{} in [{}, {})", label(2), edges[2][z], edges[2][z + 1]),
+            values: probabilities(&hist.z_slice(z))
+        }).collect();
+    write_script("slices", pages, 0, 1);
+
+    let mut export = Export::new(opts.base_name.clone(), &opts.export);
+    if !export.is_empty() {
+        let counts: Vec<u64> = hist.counts.iter().map(|&count| count as u64).collect();
+        export.array("counts", &AXES, Array::new(shape.to_vec(), counts));
+        for &(a, b) in [(0, 1), (0, 2), (1, 2)].iter()
+        {
+            // transpose from row major (b, a) to (a, b)
+            let marginal = hist.marginal(a, b);
+            let transposed: Vec<u64> = (0..shape[a])
+                .flat_map(|i| (0..shape[b]).map(move |j| (i, j)))
+                .map(|(i, j)| marginal[j * shape[a] + i] as u64)
+                .collect();
+            export.array(
+                &format!("marginal_{}{}", AXES[a], AXES[b]),
+                &[AXES[a], AXES[b]],
+                Array::new(vec![shape[a], shape[b]], transposed)
+            );
+        }
+        for (axis, edges) in edges.iter().enumerate()
+        {
+            export.array(&format!("{}_edges", AXES[axis]), &["edge"], Array::vector(edges.clone()))
+                .info(AXES[axis], &opts.values[axis]);
+        }
+        export.info("total", total)
+            .info("misses", misses);
+        if let Some(filter) = opts.filter.as_ref() {
+            export.info("where", filter)
+                .info("filtered", filtered);
+        }
+    }
+    outputs.extend(export.write(&opts.params));
+
+    let filtered = opts.filter.as_ref().map(|_| filtered);
+    report_lines("x, y or z", "histogram", filtered, frac, total);
+
+    if opts.gnuplot_exec {
+        let scripts: Vec<_> = outputs.iter()
+            .filter(|name| name.ends_with(".gp"))
+            .collect();
+        exec_gnuplot(&scripts);
+    }
+    Ok(outputs)
+}
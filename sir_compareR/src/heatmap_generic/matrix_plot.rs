@@ -0,0 +1,54 @@
+use sampling::GnuplotPalette;
+use std::io::Write;
+use crate::heatmap2::ValueAxis;
+
+/// Matrix of values, that is plotted by [write_matrix_gnuplot]
+#[derive(Debug, Clone)]
+pub struct MatrixPage{
+    /// title of the plot, empty for no title
+    pub title: String,
+    /// row major, i.e., index y * width + x
+    pub values: Vec<f64>
+}
+
+/// Writes a gnuplot script plotting each page as heatmap with `width` columns and `height` rows.
+/// All pages end up in the same pdf, one page each.
+/// `axes` map the column and row indices to values
+pub fn write_matrix_gnuplot<W: Write>(
+    mut writer: W,
+    output_name: &str,
+    pages: &[MatrixPage],
+    (width, height): (usize, usize),
+    labels: (Option<&str>, Option<&str>),
+    axes: (ValueAxis, ValueAxis),
+    palette: &GnuplotPalette
+) -> std::io::Result<()>
+{
+    writeln!(writer, "set t pdfcairo")?;
+    writeln!(writer, "set output \"{}\"", output_name)?;
+    if let Some(label) = labels.0 {
+        writeln!(writer, "set xlabel \"{}\"", label)?;
+    }
+    if let Some(label) = labels.1 {
+        writeln!(writer, "set ylabel \"{}\"", label)?;
+    }
+    writeln!(writer, "set xrange[-0.5:{}]", width as f64 - 0.5)?;
+    writeln!(writer, "set yrange[-0.5:{}]", height as f64 - 0.5)?;
+    axes.0.write_tics(&mut writer, "x", 5.min(width).max(2))?;
+    axes.1.write_tics(&mut writer, "y", 5.min(height).max(2))?;
+    palette.write_palette(&mut writer)?;
+
+    for (i, page) in pages.iter().enumerate()
+    {
+        writeln!(writer, "$data{} << EOD", i)?;
+        for row in page.values.chunks(width)
+        {
+            let row: Vec<_> = row.iter().map(|val| val.to_string()).collect();
+            writeln!(writer, "{}", row.join(" "))?;
+        }
+        writeln!(writer, "EOD")?;
+        writeln!(writer, "set title \"{}\"", page.title)?;
+        writeln!(writer, "plot $data{} matrix with image t \"\"", i)?;
+    }
+    writeln!(writer, "set output")
+}
@@ -0,0 +1,257 @@
+use sampling::*;
+use std::fmt::{self, Debug};
+use std::io::Write;
+use std::str::FromStr;
+use crate::heatmap2::ValueAxis;
+use crate::export::{Export, Array};
+use super::matrix_plot::*;
+
+/// How the values of the weight column are given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightScale{
+    /// the weight itself
+    Linear,
+    /// natural logarithm of the weight
+    Ln,
+    /// log10 of the weight
+    Log10
+}
+
+impl WeightScale{
+    /// natural logarithm of the weight given by `val`
+    pub fn ln_weight(&self, val: f64) -> f64
+    {
+        match self {
+            WeightScale::Linear => val.ln(),
+            WeightScale::Ln => val,
+            WeightScale::Log10 => val * std::f64::consts::LN_10
+        }
+    }
+}
+
+impl FromStr for WeightScale {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "linear" | "lin" => Ok(WeightScale::Linear),
+            "ln" | "log" => Ok(WeightScale::Ln),
+            "log10" => Ok(WeightScale::Log10),
+            _ => Err("Unknown weight scale. Valid: linear, ln, log10")
+        }
+    }
+}
+
+impl fmt::Display for WeightScale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            WeightScale::Linear => "linear",
+            WeightScale::Ln => "ln",
+            WeightScale::Log10 => "log10"
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Heatmap the x and y values of the data lines are counted into
+pub trait CountXY<X, Y>{
+    type Error: Debug;
+    /// Counts the point with weight `exp(ln_weight)`. Unweighted heatmaps ignore the weight
+    fn count_xy(&mut self, x: X, y: Y, ln_weight: f64) -> Result<(), Self::Error>;
+
+    /// Adds the counts of `other`, which has to have the same histograms
+    fn combine(&mut self, other: &Self);
+}
+
+impl<X, Y, Hx, Hy> CountXY<X, Y> for HeatmapU<Hx, Hy>
+where Hx: HistogramVal<X>,
+    Hy: HistogramVal<Y>
+{
+    type Error = HeatmapError;
+    fn count_xy(&mut self, x: X, y: Y, _: f64) -> Result<(), Self::Error>
+    {
+        self.count(x, y).map(|_| ())
+    }
+
+    fn combine(&mut self, other: &Self)
+    {
+        HeatmapU::combine(self, other).unwrap();
+    }
+}
+
+/// `ln(exp(a) + exp(b))` without overflow
+fn ln_sum_exp(a: f64, b: f64) -> f64
+{
+    let (max, min) = if a >= b { (a, b) } else { (b, a) };
+    if min == f64::NEG_INFINITY {
+        max
+    } else {
+        max + (min - max).exp().ln_1p()
+    }
+}
+
+/// Heatmap of weighted points. The weights are accumulated as logarithms,
+/// so that the huge weight differences of reweighted data do not overflow
+#[derive(Debug, Clone)]
+pub struct WeightedHeatmap<Hx, Hy>{
+    hist_x: Hx,
+    hist_y: Hy,
+    width: usize,
+    height: usize,
+    // row major, i.e., index y * width + x
+    ln_weights: Vec<f64>,
+    total: usize,
+    misses: usize
+}
+
+impl<Hx, Hy> WeightedHeatmap<Hx, Hy>
+where Hx: Histogram,
+    Hy: Histogram
+{
+    pub fn new(hist_x: Hx, hist_y: Hy) -> Self
+    {
+        let width = hist_x.bin_count();
+        let height = hist_y.bin_count();
+        Self{
+            hist_x,
+            hist_y,
+            width,
+            height,
+            ln_weights: vec![f64::NEG_INFINITY; width * height],
+            total: 0,
+            misses: 0
+        }
+    }
+
+    pub fn width(&self) -> usize
+    {
+        self.width
+    }
+
+    pub fn height(&self) -> usize
+    {
+        self.height
+    }
+
+    /// number of counted points, including misses
+    pub fn total(&self) -> usize
+    {
+        self.total
+    }
+
+    /// number of points outside the heatmap
+    pub fn total_misses(&self) -> usize
+    {
+        self.misses
+    }
+
+    /// natural logarithm of the accumulated weights, row major. Empty cells are -inf
+    pub fn ln_weights(&self) -> &[f64]
+    {
+        &self.ln_weights
+    }
+
+    /// Weights normalized such that every column sums to 1, row major.
+    /// With `global` all entries sum to 1 instead
+    pub fn normalized(&self, global: bool) -> Vec<f64>
+    {
+        let ln_sum = |iter: &mut dyn Iterator<Item=f64>| iter.fold(f64::NEG_INFINITY, ln_sum_exp);
+        let width = self.width;
+        let column_sums: Vec<f64> = if global {
+            vec![ln_sum(&mut self.ln_weights.iter().copied()); width]
+        } else {
+            (0..width)
+                .map(|x| ln_sum(&mut self.ln_weights.iter().skip(x).step_by(width).copied()))
+                .collect()
+        };
+        self.ln_weights.iter()
+            .enumerate()
+            .map(|(i, &ln_weight)| {
+                let sum = column_sums[i % width];
+                if sum == f64::NEG_INFINITY {
+                    0.0
+                } else {
+                    (ln_weight - sum).exp()
+                }
+            }).collect()
+    }
+
+    /// Adds the logarithms of the weights and the normalized matrix to the export.
+    /// Both have the shape (width, height), i.e., they are indexed by `[x, y]`
+    pub fn export(&self, export: &mut Export, global: bool)
+    {
+        let x_major = |row_major: &[f64]| -> Vec<f64> {
+            (0..self.width)
+                .flat_map(|x| (0..self.height).map(move |y| row_major[y * self.width + x]))
+                .collect()
+        };
+        let shape = vec![self.width, self.height];
+        export.array("ln_weights", &["x", "y"], Array::new(shape.clone(), x_major(&self.ln_weights)))
+            .array("normalized", &["x", "y"], Array::new(shape, x_major(&self.normalized(global))))
+            .info("normalization", if global { "global" } else { "columns" });
+    }
+
+    /// Writes a gnuplot script plotting the [normalized](Self::normalized) heatmap.
+    /// `x_axis` and `y_axis` map the bin indices to values
+    pub fn gnuplot<W: Write>(
+        &self,
+        writer: W,
+        output_name: &str,
+        labels: (Option<&str>, Option<&str>),
+        axes: (ValueAxis, ValueAxis),
+        palette: &GnuplotPalette,
+        global: bool
+    ) -> std::io::Result<()>
+    {
+        let page = MatrixPage{
+            title: String::new(),
+            values: self.normalized(global)
+        };
+        write_matrix_gnuplot(
+            writer,
+            output_name,
+            &[page],
+            (self.width, self.height),
+            labels,
+            axes,
+            palette
+        )
+    }
+}
+
+impl<X, Y, Hx, Hy> CountXY<X, Y> for WeightedHeatmap<Hx, Hy>
+where Hx: HistogramVal<X>,
+    Hy: HistogramVal<Y>
+{
+    type Error = HeatmapError;
+    fn count_xy(&mut self, x: X, y: Y, ln_weight: f64) -> Result<(), Self::Error>
+    {
+        self.total += 1;
+        let index = self.hist_x.get_bin_index(x)
+            .map_err(HeatmapError::XError)
+            .and_then(|x| {
+                self.hist_y.get_bin_index(y)
+                    .map(|y| y * self.width + x)
+                    .map_err(HeatmapError::YError)
+            });
+        match index {
+            Ok(index) => {
+                self.ln_weights[index] = ln_sum_exp(self.ln_weights[index], ln_weight);
+                Ok(())
+            },
+            Err(error) => {
+                self.misses += 1;
+                Err(error)
+            }
+        }
+    }
+
+    fn combine(&mut self, other: &Self)
+    {
+        assert_eq!(self.ln_weights.len(), other.ln_weights.len(), "WeightedHeatmap: dimensions do not match");
+        self.ln_weights.iter_mut()
+            .zip(other.ln_weights.iter())
+            .for_each(|(this, &other)| *this = ln_sum_exp(*this, other));
+        self.total += other.total;
+        self.misses += other.misses;
+    }
+}
@@ -1,5 +1,7 @@
 mod helper;
 mod parse_files;
+mod tdigest;
 
 pub use helper::*;
-pub use parse_files::*;
\ No newline at end of file
+pub use parse_files::*;
+pub use tdigest::*;
\ No newline at end of file
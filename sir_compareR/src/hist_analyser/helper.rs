@@ -1,6 +1,22 @@
 use sampling::*;
+use crate::histogram::{sort_without_nan, quantile_sorted, bootstrap_interval};
+use rand_pcg::Pcg64;
+use rand::SeedableRng;
+use rayon::prelude::*;
+use indicatif::*;
+use std::io::Write;
 
 
+/// Collects the features of the curves by energy bin, see [HistSampler] and [HistDigest]
+pub trait FeatureCollector: Clone + Send + Sync
+{
+    fn count_feature(&mut self, energy: usize, val: f64);
+
+    /// adds the values of `other`, which has to use the same energy bins
+    fn combine(&mut self, other: &Self);
+}
+
+/// Stores all values, binned by energy, to calculate percentiles
 #[derive(Debug, Clone)]
 pub struct HistSampler<V, H>
 {
@@ -44,18 +60,23 @@ where H: Histogram + HistogramVal<usize>
     }
 }
 
-impl<H> HistSampler<usize, H>
+impl<H> HistSampler<f64, H>
 where H: Histogram + HistogramVal<usize>
 {
-    pub fn percent(&mut self, p: f64) -> Vec<PercentResult>
+    /// Linearly interpolated percentiles `ps` of the values of each energy bin.
+    /// NaN values are removed, empty bins result in NaN
+    pub fn percentiles(&mut self, ps: &[f64]) -> Vec<PercentResult>
     {
         self.binned_vals
             .iter_mut()
-            .for_each(|vec| vec.sort_unstable());
+            .for_each(sort_without_nan);
 
-        
-        let gegen_wk = 1.0 - p;
-        let min_needed = 1.0 / gegen_wk;
+        // the most extreme percentile needs the most values,
+        // minimum and maximum (p = 0 or 1) are defined for any bin with values
+        let min_needed = ps.iter()
+            .filter(|&&p| p > 0.0 && p < 1.0)
+            .map(|&p| 1.0 / p.min(1.0 - p))
+            .fold(1.0, f64::max);
         let min_count_needed = min_needed.ceil() as usize; 
         if self.binned_vals.iter().any(|v| v.len() < min_count_needed)
         {
@@ -67,11 +88,6 @@ where H: Histogram + HistogramVal<usize>
             }
         }
         
-        let percent_vec: Vec<_> = self.binned_vals
-            .iter()
-            .map(|vec| percent_helper(vec, p))
-            .collect();
-        
         let bins = self.hist.borders_clone()
             .unwrap();
         let bins_left = &bins[..];
@@ -79,19 +95,40 @@ where H: Histogram + HistogramVal<usize>
 
         bins_left.iter()
             .zip(bins_right.iter())
-            .zip(percent_vec.iter())
-            .zip(self.binned_vals.iter().map(|v| v.len()))
-            .map(|(((left, right), val), len)| {
+            .zip(self.binned_vals.iter())
+            .map(|((left, right), vals)| {
                 PercentResult{
                     left: *left,
                     right: *right,
-                    time: *val,
-                    count: len
+                    values: ps.iter()
+                        .map(|&p| quantile_sorted(vals, p))
+                        .collect(),
+                    intervals: Vec::new(),
+                    count: vals.len()
                 }
             }).collect()
         
     }
 
+    /// Like [percentiles](Self::percentiles), additionally with bootstrapped 
+    /// confidence intervals for each percentile of each energy bin.
+    /// Bins are bootstrapped in parallel
+    pub fn percentiles_bootstrap(&mut self, ps: &[f64], config: &PercentBootstrap) -> Vec<PercentResult>
+    {
+        let mut res = self.percentiles(ps);
+        println!("bootstrapping percentiles");
+        let intervals: Vec<_> = self.binned_vals
+            .par_iter()
+            .enumerate()
+            .progress()
+            .map(|(index, vals)| config.estimate(index, vals, ps))
+            .collect();
+        res.iter_mut()
+            .zip(intervals)
+            .for_each(|(item, intervals)| item.intervals = intervals);
+        res
+    }
+
     pub fn dirty_add(&mut self, other: &Self){
         self.miss_count += other.miss_count;
         self.binned_vals
@@ -106,19 +143,122 @@ where H: Histogram + HistogramVal<usize>
     }
 }
 
-/// assumes sorted slice
-fn percent_helper(slice: &[usize], p: f64) -> usize
+impl<H> FeatureCollector for HistSampler<f64, H>
+where H: Histogram + HistogramVal<usize> + Clone + Send + Sync
 {
-    let len = slice.len();
-    let index = (len as f64 * p).ceil() as usize - 1;
-    assert!(index < len);
-    slice[index]
+    fn count_feature(&mut self, energy: usize, val: f64)
+    {
+        self.count(energy, val)
+    }
+
+    fn combine(&mut self, other: &Self)
+    {
+        self.dirty_add(other)
+    }
 }
 
+/// Percentile of one energy bin
 #[derive(Debug, Clone)]
 pub struct PercentResult{
     pub left: usize,
     pub right: usize,
-    pub time: usize,
+    /// one value for each requested percentile
+    pub values: Vec<f64>,
+    /// confidence interval for each requested percentile, empty if not bootstrapped
+    pub intervals: Vec<PercentInterval>,
     pub count: usize
+}
+
+impl PercentResult{
+    /// number of percentiles of this bin with a confidence interval wider than `max_width`
+    pub fn wide_intervals(&self, max_width: f64) -> usize
+    {
+        self.intervals
+            .iter()
+            .filter(|interval| interval.width() > max_width)
+            .count()
+    }
+}
+
+/// Bootstrapped error and confidence interval of one percentile
+#[derive(Debug, Clone, Copy)]
+pub struct PercentInterval{
+    pub error: f64,
+    pub lower: f64,
+    pub upper: f64
+}
+
+impl PercentInterval{
+    fn nan() -> Self
+    {
+        Self{
+            error: f64::NAN,
+            lower: f64::NAN,
+            upper: f64::NAN
+        }
+    }
+
+    pub fn width(&self) -> f64
+    {
+        self.upper - self.lower
+    }
+}
+
+/// Settings for the bootstrapped confidence intervals of the percentiles
+#[derive(Debug, Clone, Copy)]
+pub struct PercentBootstrap{
+    /// number of bootstrap resamples
+    pub samples: usize,
+    /// the rng of bin i is seeded with seed + i
+    pub seed: u64,
+    /// confidence level of the interval, e.g. 0.95 for the 2.5 and 97.5 percentiles
+    pub confidence: f64,
+    /// bins with a wider interval are flagged
+    pub max_width: Option<f64>
+}
+
+impl PercentBootstrap{
+    /// Write the parameters as comment lines
+    pub fn write_header<W: Write>(&self, mut writer: W) -> std::io::Result<()>
+    {
+        writeln!(
+            writer,
+            "#bootstrap samples: {} seed: {} confidence: {}",
+            self.samples,
+            self.seed,
+            self.confidence
+        )?;
+        writeln!(writer, "#lower and upper are percentiles of the bootstrap distribution, error is its standard deviation")?;
+        if let Some(max_width) = self.max_width {
+            writeln!(writer, "#wide: number of percentiles with upper - lower > {}", max_width)?;
+        }
+        Ok(())
+    }
+
+    /// Confidence interval of each percentile `ps` of the `sorted` values of bin `index`,
+    /// see [bootstrap_interval]
+    fn estimate(&self, index: usize, sorted: &[f64], ps: &[f64]) -> Vec<PercentInterval>
+    {
+        if sorted.is_empty() {
+            return vec![PercentInterval::nan(); ps.len()];
+        }
+        ps.iter()
+            .map(
+                |&p|
+                {
+                    // same seed for each percentile, i.e., all percentiles are estimated from the same resamples
+                    let rng = Pcg64::seed_from_u64(self.seed.wrapping_add(index as u64));
+                    let percentile = |sample: &mut [f64]| {
+                        sample.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+                        quantile_sorted(sample, p)
+                    };
+                    let estimate = bootstrap_interval(rng, self.samples, self.confidence, sorted, percentile);
+                    PercentInterval{
+                        error: estimate.error,
+                        lower: estimate.lower,
+                        upper: estimate.upper
+                    }
+                }
+            ).collect()
+    }
 }
\ No newline at end of file
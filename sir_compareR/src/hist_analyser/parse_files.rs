@@ -1,9 +1,5 @@
-use crate::PercentOpts;
-use lzma::LzmaReader;
-use flate2::read::*;
+use crate::input::open_input;
 use std::io::*;
-use std::fs::*;
-use sampling::*;
 use std::result::Result;
 use std::path::Path;
 use indicatif::*;
@@ -12,15 +8,15 @@ use crate::hist_analyser::*;
 use rayon::prelude::*;
 
 
-fn parse_and_count<R>
+fn parse_and_count<R, C>
 (
     reader: R, 
-    every: usize, 
-    fun: FunctionChooser,
-    hist_percent: &mut HistSampler<usize, HistUsize>
+    spec: &FeatureSpec,
+    hist_percent: &mut C
 )
 where
     R: Read,
+    C: FeatureCollector,
 {
     let buf_reader = BufReader::new(reader);
 
@@ -32,99 +28,67 @@ where
                 !line.trim_start().starts_with('#') // skip comments
                 && !line.is_empty()
             }
-        ).step_by(every)
-        .for_each( |line|
-            {
-                let slice = line.trim();
-                
-                parse_into_percent_res(slice, fun, hist_percent);
-                
-            }
-        );
+        ).step_by(spec.every)
+        .filter_map(|line| CurveLine::parse(line.trim()))
+        .filter(|curve| spec.keep(curve))
+        .for_each(|curve| parse_into_percent_res(&curve, spec, hist_percent));
 }
 
-pub(crate) fn parse_into_percent_res
+/// Counts the feature of the curve
+pub(crate) fn parse_into_percent_res<C>
 (
-    slice: &str,
-    fun: FunctionChooser,
-    hist_percent: &mut HistSampler<usize, HistUsize>
+    curve: &CurveLine,
+    spec: &FeatureSpec,
+    hist_percent: &mut C
 )
+where C: FeatureCollector
 {
-    let mut it = slice.split_whitespace();
-    let energy = it.next().unwrap();
-    
-    let energy = energy.parse::<usize>().unwrap();
-
-    let iter = slice
-        .split_whitespace()
-        .skip(2)
-        .map(|v| v.parse::<usize>().unwrap());
-    
-    let val = fun.usize_exec(iter);
-
-    hist_percent.count(energy, val);
+    if let Some(val) = spec.feature(curve) {
+        hist_percent.count_feature(curve.energy, val);
+    }
 }
 
-pub fn parse_and_count_all_files(opts: &PercentOpts) -> HistSampler<usize, HistUsize>
+/// Bins the feature of each curve of all files matching `files` by energy into the empty `hist_percent`.
+/// Files are parsed in parallel
+pub fn parse_and_count_all_files<C>(files: &str, spec: &FeatureSpec, mut hist_percent: C) -> C
+where C: FeatureCollector
 {
     
-    let files: Vec<_> = glob::glob(&opts.files)
+    let files: Vec<_> = glob::glob(files)
         .unwrap()
         .filter_map(Result::ok)
         .collect();
     
-    let energy_hist = HistUsize::new(1, opts.n + 1, opts.bins)
-        .expect("failed to create energy hist");
-
-    let mut hist_percent = HistSampler::new(energy_hist);
-    
     let hist_percent_vec: Vec<_> = files.par_iter()
         .progress()
         .map(|entry|
             {
                 let mut tmp_hist_percent = hist_percent.clone();
-                parse_and_count_file(entry, opts.every, opts.fun, &mut tmp_hist_percent);
+                parse_and_count_file(entry, spec, &mut tmp_hist_percent);
                 tmp_hist_percent
             }
         ).collect();
     
     for other in hist_percent_vec {
-        hist_percent.dirty_add(&other)
+        hist_percent.combine(&other)
     }
+    report_misses();
     
     hist_percent
 }
 
 
 
-pub fn parse_and_count_file<P>
+pub fn parse_and_count_file<P, C>
 (
     filename: P,
-    every: usize,
-    fun: FunctionChooser,
-    hist_percent: &mut HistSampler<usize, HistUsize>
+    spec: &FeatureSpec,
+    hist_percent: &mut C
 )
 where P: AsRef<Path>,
+    C: FeatureCollector,
 {
-    let ending = filename.as_ref()
-        .extension()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let file = File::open(&filename).unwrap();
-
-    match ending {
-        "gz" => {
-            let decoder = GzDecoder::new(file);
-            parse_and_count(decoder, every, fun, hist_percent)
-        },
-        "xz" => {
-            let decoder = LzmaReader::new_decompressor(file).unwrap();
-            parse_and_count(decoder, every, fun, hist_percent)
-        },
-        _ => {
-            parse_and_count(file, every, fun, hist_percent)
-        }
-    }
+    let reader = open_input(filename);
+    parse_and_count(reader, spec, hist_percent)
 
 }
\ No newline at end of file
@@ -0,0 +1,325 @@
+use sampling::*;
+use std::f64::consts::PI;
+use super::*;
+
+/// Cluster of values of a [TDigest]
+#[derive(Debug, Clone, Copy)]
+struct Centroid{
+    mean: f64,
+    weight: f64
+}
+
+/// Mergeable streaming quantile sketch (merging t-digest).
+/// Memory is bounded by the compression instead of the number of values,
+/// quantiles close to 0 and 1 are the most accurate
+#[derive(Debug, Clone)]
+pub struct TDigest{
+    compression: f64,
+    centroids: Vec<Centroid>,
+    /// unmerged values and centroids
+    buffer: Vec<Centroid>,
+    buffer_cap: usize,
+    count: f64,
+    min: f64,
+    max: f64
+}
+
+impl TDigest{
+    /// Higher compression means more centroids, i.e., more accurate quantiles and more memory
+    pub fn new(compression: f64) -> Self
+    {
+        let buffer_cap = (5.0 * compression).ceil() as usize;
+        Self{
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::with_capacity(buffer_cap),
+            buffer_cap,
+            count: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY
+        }
+    }
+
+    /// number of added values
+    pub fn count(&self) -> usize
+    {
+        self.count as usize
+    }
+
+    pub fn add(&mut self, val: f64)
+    {
+        self.count += 1.0;
+        self.min = self.min.min(val);
+        self.max = self.max.max(val);
+        self.buffer.push(Centroid{mean: val, weight: 1.0});
+        if self.buffer.len() >= self.buffer_cap {
+            self.compress();
+        }
+    }
+
+    /// adds all values of `other`
+    pub fn merge(&mut self, other: &Self)
+    {
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        self.compress();
+    }
+
+    /// scale function k_1, limits the size of the centroids
+    fn k(&self, q: f64) -> f64
+    {
+        self.compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+    }
+
+    fn k_inv(&self, k: f64) -> f64
+    {
+        ((k * 2.0 * PI / self.compression).sin() + 1.0) / 2.0
+    }
+
+    /// merges the buffer into the centroids
+    fn compress(&mut self)
+    {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.buffer);
+        all.sort_unstable_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap());
+
+        let total: f64 = all.iter().map(|c| c.weight).sum();
+        let mut iter = all.into_iter();
+        let mut current = iter.next().unwrap();
+        let mut weight_before = 0.0;
+        let mut q_limit = self.k_inv(self.k(0.0) + 1.0);
+        for next in iter {
+            let q = (weight_before + current.weight + next.weight) / total;
+            if q <= q_limit {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                weight_before += current.weight;
+                self.centroids.push(current);
+                q_limit = self.k_inv(self.k(weight_before / total) + 1.0);
+                current = next;
+            }
+        }
+        self.centroids.push(current);
+    }
+
+    /// Quantile q, linearly interpolated between the centers of the centroids.
+    /// Exact as long as every centroid holds a single value.
+    /// NaN if no values were added
+    pub fn quantile(&mut self, q: f64) -> f64
+    {
+        self.compress();
+        if self.centroids.is_empty() {
+            return f64::NAN;
+        }
+        // ranks 0, ..., count - 1, centroid i covers weight_i consecutive ranks
+        let pos = q * (self.count - 1.0);
+        let mut left_rank = 0.0;
+        let mut left_val = self.min;
+        let mut weight_before = 0.0;
+        for c in self.centroids.iter() {
+            let center = weight_before + (c.weight - 1.0) / 2.0;
+            if pos <= center {
+                return interpolate(left_rank, left_val, center, c.mean, pos);
+            }
+            left_rank = center;
+            left_val = c.mean;
+            weight_before += c.weight;
+        }
+        interpolate(left_rank, left_val, self.count - 1.0, self.max, pos)
+    }
+}
+
+fn interpolate(x_left: f64, y_left: f64, x_right: f64, y_right: f64, x: f64) -> f64
+{
+    if x_right <= x_left {
+        y_right
+    } else {
+        y_left + (x - x_left) / (x_right - x_left) * (y_right - y_left)
+    }
+}
+
+/// One [TDigest] per energy bin, alternative to [HistSampler]
+/// that does not store the values
+#[derive(Debug, Clone)]
+pub struct HistDigest<H>
+{
+    hist: H,
+    digests: Vec<TDigest>,
+    miss_count: usize
+}
+
+impl<H> HistDigest<H>
+where H: Histogram
+{
+    pub fn new(hist: H, compression: f64) -> Self
+    {
+        let digests = vec![TDigest::new(compression); hist.bin_count()];
+        Self{
+            hist,
+            digests,
+            miss_count: 0
+        }
+    }
+}
+
+impl<H> HistDigest<H>
+where H: Histogram + HistogramVal<usize>
+{
+    /// NaN values are ignored
+    pub fn count(&mut self, energy: usize, val: f64)
+    {
+        if val.is_nan() {
+            return;
+        }
+        match self.hist.count_val(energy) {
+            Ok(index) => self.digests[index].add(val),
+            Err(_) => self.miss_count += 1
+        }
+    }
+
+    /// Estimated percentiles `ps` of the values of each energy bin.
+    /// Empty bins result in NaN
+    pub fn percentiles(&mut self, ps: &[f64]) -> Vec<PercentResult>
+    {
+        let bins = self.hist.borders_clone()
+            .unwrap();
+
+        bins.iter()
+            .zip(bins[1..].iter())
+            .zip(self.digests.iter_mut())
+            .map(|((left, right), digest)| {
+                PercentResult{
+                    left: *left,
+                    right: *right,
+                    values: ps.iter()
+                        .map(|&p| digest.quantile(p))
+                        .collect(),
+                    intervals: Vec::new(),
+                    count: digest.count()
+                }
+            }).collect()
+    }
+}
+
+impl<H> FeatureCollector for HistDigest<H>
+where H: Histogram + HistogramVal<usize> + Clone + Send + Sync
+{
+    fn count_feature(&mut self, energy: usize, val: f64)
+    {
+        self.count(energy, val)
+    }
+
+    fn combine(&mut self, other: &Self)
+    {
+        self.miss_count += other.miss_count;
+        self.digests
+            .iter_mut()
+            .zip(other.digests.iter())
+            .for_each(|(this, other)| this.merge(other));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PS: [f64; 7] = [0.0, 0.01, 0.1, 0.5, 0.9, 0.99, 1.0];
+
+    /// 0, ..., n - 1 in scrambled order, shifted by 1000 * energy
+    fn values(energy: usize, n: usize) -> impl Iterator<Item = (usize, f64)>
+    {
+        (0..n).map(move |i| (energy, ((i * 7919) % n + 1000 * energy) as f64))
+    }
+
+    fn data() -> Vec<(usize, f64)>
+    {
+        values(0, 1000)
+            .chain(values(1, 500))
+            .collect()
+    }
+
+    fn digest_of(data: &[(usize, f64)], compression: f64) -> HistDigest<HistUsize>
+    {
+        let mut digest = HistDigest::new(HistUsize::new(0, 2, 2).unwrap(), compression);
+        for &(energy, val) in data {
+            digest.count(energy, val);
+        }
+        digest
+    }
+
+    #[test]
+    fn digest_matches_sampler()
+    {
+        let data = data();
+        let mut sampler = HistSampler::new(HistUsize::new(0, 2, 2).unwrap());
+        for &(energy, val) in data.iter() {
+            sampler.count(energy, val);
+        }
+        let exact = sampler.percentiles(&PS);
+
+        for &compression in [20.0, 100.0, 500.0].iter() {
+            let estimated = digest_of(&data, compression).percentiles(&PS);
+            // the values of the bins span 1000 and 500
+            for ((exact, estimated), range) in exact.iter().zip(estimated.iter()).zip([1000.0, 500.0].iter()) {
+                assert_eq!(exact.count, estimated.count);
+                assert_eq!((exact.left, exact.right), (estimated.left, estimated.right));
+                // minimum and maximum are exact
+                assert_eq!(exact.values[0], estimated.values[0]);
+                assert_eq!(exact.values[6], estimated.values[6]);
+                for (p, (a, b)) in PS.iter().zip(exact.values.iter().zip(estimated.values.iter())) {
+                    assert!(
+                        (a - b).abs() <= 0.5 * range / compression,
+                        "compression {} p {}: exact {} estimated {}", compression, p, a, b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_order()
+    {
+        let data = data();
+        let compression = 50.0;
+        let parts: Vec<_> = data.chunks(200)
+            .map(|chunk| digest_of(chunk, compression))
+            .collect();
+
+        let mut forward = parts[0].clone();
+        parts[1..].iter().for_each(|part| forward.combine(part));
+        let mut backward = parts[parts.len() - 1].clone();
+        parts[..parts.len() - 1].iter().rev().for_each(|part| backward.combine(part));
+        let mut whole = digest_of(&data, compression);
+
+        // the centroids depend on the order, the estimates agree within the accuracy of the digest
+        let whole = whole.percentiles(&PS);
+        for merged in [forward.percentiles(&PS), backward.percentiles(&PS)].iter() {
+            for ((a, b), range) in whole.iter().zip(merged.iter()).zip([1000.0, 500.0].iter()) {
+                assert_eq!(a.count, b.count);
+                assert_eq!(a.values[0], b.values[0]);
+                assert_eq!(a.values[6], b.values[6]);
+                for (x, y) in a.values.iter().zip(b.values.iter()) {
+                    assert!((x - y).abs() <= 0.005 * range, "{:?} vs {:?}", a.values, b.values);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nan_is_ignored()
+    {
+        let mut digest = digest_of(&[(0, 1.0), (0, f64::NAN), (0, 3.0)], 100.0);
+        let res = digest.percentiles(&[0.5]);
+        assert_eq!(res[0].count, 2);
+        assert_eq!(res[0].values[0], 2.0);
+        assert!(res[1].values[0].is_nan());
+    }
+}
@@ -1,22 +1,347 @@
 
- 
+
 use rand_pcg::Pcg64;
-use rand::SeedableRng;
+use rand::{SeedableRng, Rng};
 use crate::histogram::*;
 use rayon::iter::*;
 use indicatif::*;
-use average::Mean;
+use average::{Mean, Variance};
 use sampling::bootstrap_copyable;
+use rgsl::randist::gaussian::gaussian_Pinv;
+use std::str::FromStr;
+use std::io::Write;
+use std::fmt;
+use structopt::StructOpt;
+
+/// How the error of the mean of each bin is estimated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorMethod{
+    Bootstrap,
+    Jackknife
+}
+
+impl FromStr for ErrorMethod {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bootstrap" | "b" => Ok(ErrorMethod::Bootstrap),
+            "jackknife" | "j" => Ok(ErrorMethod::Jackknife),
+            _ => Err("Invalid ErrorMethod requested, valid: bootstrap, jackknife")
+        }
+    }
+}
+
+impl fmt::Display for ErrorMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorMethod::Bootstrap => write!(f, "bootstrap"),
+            ErrorMethod::Jackknife => write!(f, "jackknife")
+        }
+    }
+}
+
+/// Settings for the error estimation of [histogramm_parallel]
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorConfig{
+    pub method: ErrorMethod,
+    /// number of bootstrap resamples
+    pub samples: usize,
+    /// the rng of bin i is seeded with seed + i
+    pub seed: u64,
+    /// confidence level of the interval, e.g. 0.95 for the 2.5 and 97.5 percentiles
+    pub confidence: f64,
+    /// additionally calculate and write the confidence interval
+    pub interval: bool
+}
+
+impl Default for ErrorConfig{
+    fn default() -> Self {
+        Self{
+            method: ErrorMethod::Bootstrap,
+            samples: 200,
+            seed: 0,
+            confidence: 0.95,
+            interval: false
+        }
+    }
+}
+
+// Command line options defining the [ErrorConfig], shared by Histogram and Multi
+#[derive(Debug, Clone, Copy, StructOpt)]
+pub struct ErrorArgs{
+    /// How to estimate the error of the mean.
+    /// valid: bootstrap, jackknife
+    #[structopt(long, default_value = "bootstrap")]
+    pub error_method: ErrorMethod,
+
+    /// number of bootstrap resamples
+    #[structopt(long, default_value = "200")]
+    pub bootstrap_samples: usize,
+
+    /// seed for the bootstrap, bin i uses seed + i
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+
+    /// confidence level of the interval, 
+    /// e.g. 0.95 for the 2.5 and 97.5 percentiles
+    #[structopt(long, default_value = "0.95")]
+    pub confidence: f64,
+
+    /// additionally write the lower and upper end of the confidence interval
+    #[structopt(long)]
+    pub interval: bool
+}
+
+impl ErrorArgs{
+    /// Error, if the options are invalid
+    pub fn config(&self) -> Result<ErrorConfig, String>
+    {
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return Err(format!("confidence has to be in (0, 1), found {}", self.confidence));
+        }
+        if self.error_method == ErrorMethod::Bootstrap && self.bootstrap_samples == 0 {
+            return Err("bootstrap_samples has to be at least 1".to_owned());
+        }
+        Ok(
+            ErrorConfig{
+                method: self.error_method,
+                samples: self.bootstrap_samples,
+                seed: self.seed,
+                confidence: self.confidence,
+                interval: self.interval
+            }
+        )
+    }
+}
+
+impl ErrorConfig{
+    /// Write method and parameters as comment lines
+    pub fn write_header<W: Write>(&self, mut writer: W) -> std::io::Result<()>
+    {
+        match self.method {
+            ErrorMethod::Bootstrap => {
+                writeln!(
+                    writer,
+                    "#error_method: {} samples: {} seed: {}",
+                    self.method,
+                    self.samples,
+                    self.seed
+                )?;
+            },
+            ErrorMethod::Jackknife => writeln!(writer, "#error_method: {}", self.method)?
+        }
+        if !self.interval {
+            return writeln!(writer, "#index mean error");
+        }
+        match self.method {
+            ErrorMethod::Bootstrap => writeln!(
+                writer,
+                "#confidence: {}, lower and upper are percentiles of the bootstrap distribution",
+                self.confidence
+            )?,
+            ErrorMethod::Jackknife => writeln!(
+                writer,
+                "#confidence: {}, lower and upper are mean -+ z * error, normal approximation",
+                self.confidence
+            )?
+        }
+        writeln!(writer, "#index mean error lower upper")
+    }
+
+    fn estimate(&self, index: usize, data: &[f64]) -> BinEstimate
+    {
+        let mean = |data: &mut [f64]| {
+            let mean: Mean = data.iter().collect();
+            mean.mean()
+        };
+        match self.method {
+            ErrorMethod::Bootstrap => {
+                let rng = Pcg64::seed_from_u64(self.seed.wrapping_add(index as u64));
+                if self.interval {
+                    bootstrap_interval(rng, self.samples, self.confidence, data, mean)
+                } else {
+                    let (mean, error) = bootstrap_copyable(rng, self.samples, data, mean);
+                    BinEstimate{
+                        mean,
+                        error,
+                        lower: f64::NAN,
+                        upper: f64::NAN
+                    }
+                }
+            },
+            ErrorMethod::Jackknife => jackknife(self.confidence, data)
+        }
+    }
+}
+
+/// Mean of a bin together with its error and confidence interval
+#[derive(Debug, Clone, Copy)]
+pub struct BinEstimate{
+    pub mean: f64,
+    pub error: f64,
+    pub lower: f64,
+    pub upper: f64
+}
+
+impl BinEstimate{
+    fn nan() -> Self
+    {
+        Self{
+            mean: f64::NAN,
+            error: f64::NAN,
+            lower: f64::NAN,
+            upper: f64::NAN
+        }
+    }
+}
 
-pub fn histogramm_parallel(hist_data: Histogram, num_threds: usize, p_bar: bool) -> Vec<(f64, f64)>
+/// value at quantile q of sorted data, linear interpolation between neighbors.
+/// NaN for empty data
+pub(crate) fn quantile_sorted(sorted: &[f64], q: f64) -> f64
+{
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let pos = q * (sorted.len() - 1) as f64;
+    let left = pos.floor() as usize;
+    let right = pos.ceil() as usize;
+    let frac = pos - left as f64;
+    sorted[left] * (1.0 - frac) + sorted[right] * frac
+}
+
+/// Sorts the values, NaN values are removed
+pub(crate) fn sort_without_nan(values: &mut Vec<f64>)
+{
+    values.retain(|val| !val.is_nan());
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+}
+
+/// [bootstrap_copyable] of the estimator `f`, which returns mean and error of the resampled estimates.
+/// The estimates are recorded as well, lower and upper are their percentiles
+/// according to the `confidence`.
+/// Used for the Histogram and the Percent subcommand
+pub(crate) fn bootstrap_interval<R, T, F>(rng: R, samples: usize, confidence: f64, data: &[T], mut f: F) -> BinEstimate
+where R: Rng,
+    T: Copy,
+    F: FnMut(&mut [T]) -> f64
+{
+    let mut estimates = Vec::with_capacity(samples);
+    let (mean, error) = bootstrap_copyable(
+        rng,
+        samples,
+        data,
+        |sample| {
+            let estimate = f(sample);
+            estimates.push(estimate);
+            estimate
+        }
+    );
+    sort_without_nan(&mut estimates);
+    let alpha = 1.0 - confidence;
+    BinEstimate{
+        mean,
+        error,
+        lower: quantile_sorted(&estimates, alpha / 2.0),
+        upper: quantile_sorted(&estimates, 1.0 - alpha / 2.0)
+    }
+}
+
+fn jackknife(confidence: f64, data: &[f64]) -> BinEstimate
+{
+    let len = data.len();
+    if len < 2 {
+        return BinEstimate::nan();
+    }
+    let sum: f64 = data.iter().sum();
+    let mean = sum / len as f64;
+    // leave one out means
+    let squares: f64 = data.iter()
+        .map(
+            |val|
+            {
+                let diff = (sum - val) / (len - 1) as f64 - mean;
+                diff * diff
+            }
+        ).sum();
+    let error = ((len - 1) as f64 / len as f64 * squares).sqrt();
+    let z = gaussian_Pinv(1.0 - (1.0 - confidence) / 2.0, 1.0);
+    BinEstimate{
+        mean,
+        error,
+        lower: mean - z * error,
+        upper: mean + z * error
+    }
+}
+
+/// Distribution of the values of one bin, NaN values are ignored
+#[derive(Debug, Clone)]
+pub struct BinStats{
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    /// same order as the requested quantiles
+    pub quantiles: Vec<f64>
+}
+
+impl BinStats{
+    pub fn new(data: &[f64], quantiles: &[f64]) -> Self
+    {
+        let mut sorted = data.to_vec();
+        sort_without_nan(&mut sorted);
+        if sorted.is_empty() {
+            return Self{
+                count: 0,
+                mean: f64::NAN,
+                std_dev: f64::NAN,
+                min: f64::NAN,
+                max: f64::NAN,
+                quantiles: vec![f64::NAN; quantiles.len()]
+            };
+        }
+        let var: Variance = sorted.iter().collect();
+        Self{
+            count: sorted.len(),
+            mean: var.mean(),
+            std_dev: var.sample_variance().sqrt(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            quantiles: quantiles.iter()
+                .map(|&q| quantile_sorted(&sorted, q))
+                .collect()
+        }
+    }
+}
+
+/// [BinStats] of each bin, calculated with `num_threds` threads
+pub fn bin_stats(hist_data: &Histogram, quantiles: &[f64], num_threds: usize) -> Vec<BinStats>
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threds)
+        .build()
+        .unwrap();
+
+    pool.install(||
+        {
+            hist_data.hist()
+                .par_iter()
+                .map(|data| BinStats::new(data, quantiles))
+                .collect()
+        }
+    )
+}
+
+/// Mean, error and confidence interval of each bin, see [ErrorConfig]
+pub fn histogramm_parallel(hist_data: Histogram, num_threds: usize, p_bar: bool, config: ErrorConfig) -> Vec<BinEstimate>
 {
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(num_threds)
         .build()
         .unwrap();
-    
+
         let len = hist_data.hist().len();
-    
+
     let mut results = Vec::with_capacity(len);
     let bar = if p_bar{
         let b = ProgressBar::new(len as u64);
@@ -28,23 +353,18 @@ pub fn histogramm_parallel(hist_data: Histogram, num_threds: usize, p_bar: bool)
     };
     pool.install(||
         {
-            let mean = |data: &mut [f64]| {
-                let mean: Mean = data.iter().collect();
-                mean.mean()
-            };
             let v: Vec<_> = (0..len).collect();
             v.par_iter()
                 .map(
-                    |&index| 
+                    |&index|
                     {
-                        let rng = Pcg64::seed_from_u64(index as u64);
-                        let result = bootstrap_copyable(rng, 200, &hist_data.hist()[index],  mean);
+                        let result = config.estimate(index, &hist_data.hist()[index]);
                         for b in bar.iter(){
                             b.inc(1);
                         }
                         (index, result)
                     }
-                    
+
                 ).collect_into_vec(&mut results);
         }
     );
@@ -52,11 +372,10 @@ pub fn histogramm_parallel(hist_data: Histogram, num_threds: usize, p_bar: bool)
     into_reduced_hist(results)
 }
 
-pub fn into_reduced_hist(mut vec: Vec<(usize, (f64, f64))>) -> Vec<(f64, f64)>
+pub fn into_reduced_hist(mut vec: Vec<(usize, BinEstimate)>) -> Vec<BinEstimate>
 {
     vec.sort_unstable_by_key(|entry| entry.0);
     vec.into_iter()
-        .map(|(_, (mean, error))| (mean, error))
+        .map(|(_, estimate)| estimate)
         .collect()
 }
-
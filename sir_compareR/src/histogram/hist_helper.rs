@@ -1,17 +1,47 @@
 
-use std::{str::FromStr, cmp::Ordering};
+use std::{str::FromStr, cmp::Ordering, fmt};
+use crate::expression::{Expression, Curve};
+use crate::crossing::{Crossing, Flank, Threshold, max_helper};
 
-#[derive(Debug, Clone, Copy)]
+/// Reduces a curve to a single value
+#[derive(Debug, Clone)]
 pub enum HistReduce{
     IndexMax,
     ValMax,
     Median,
+    /// outbreak duration, i.e., the extinction index. Unfinished curves are skipped
+    Duration,
+    /// area under the curve, trapezoidal rule
+    Auc,
+    /// time at which half the maximum is reached on the rising flank
+    HalfRise,
+    /// time at which the curve falls below half the maximum
+    HalfFall,
+    /// full width at half maximum, i.e., HalfFall - HalfRise
+    Fwhm,
+    /// number of local maxima with at least the given prominence
+    Peaks(f64),
+    /// expression over the features, see [Expression]
+    Expression(Expression),
 }
 
 impl HistReduce {
-    pub fn reduce(&self, arr: &[f64]) -> f64
+    /// Has to be called before evaluating expressions containing `n`
+    pub fn bind_n(&mut self, n: usize)
     {
-        match self {
+        if let Self::Expression(expr) = self {
+            expr.bind_n(n);
+        }
+    }
+
+    /// Reduces the curve. Returns None if the value is not defined for this curve,
+    /// e.g., the duration of an unfinished trajectory or an expression evaluating to NaN
+    pub fn reduce(&self, arr: &[f64], energy: usize, extinction_index: usize) -> Option<f64>
+    {
+        if arr.is_empty() {
+            return None;
+        }
+        let res = match self {
             HistReduce::IndexMax => max_index(arr) as f64,
             HistReduce::ValMax => max_val(arr),
             HistReduce::Median => {
@@ -21,24 +51,76 @@ impl HistReduce {
                     |a,b| a.partial_cmp(b).unwrap()
                 );
                 clone[mid]
+            },
+            HistReduce::Duration => {
+                if extinction_index == usize::MAX {
+                    return None;
+                }
+                extinction_index as f64
+            },
+            HistReduce::Auc => {
+                let sum: f64 = arr.iter().sum();
+                sum - 0.5 * (arr[0] + arr[arr.len() - 1])
+            },
+            HistReduce::HalfRise => half_max(Flank::Rising).time(arr)?,
+            HistReduce::HalfFall => half_max(Flank::Falling).time(arr)?,
+            HistReduce::Fwhm => half_max(Flank::Falling).time(arr)? - half_max(Flank::Rising).time(arr)?,
+            HistReduce::Peaks(prominence) => count_peaks(arr, *prominence) as f64,
+            HistReduce::Expression(expr) => {
+                let curve = Curve{
+                    values: arr,
+                    energy,
+                    extinction_index: Some(extinction_index)
+                };
+                let res = expr.eval(&curve);
+                if res.is_nan() {
+                    return None;
+                }
+                res
             }
-        }
+        };
+        Some(res)
     }
 }
 
 impl FromStr for HistReduce {
-    type Err = &'static str;
+    type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        
-        match s.to_lowercase().as_str() {
+        let lower = s.to_lowercase();
+        if let Some(prominence) = lower.strip_prefix("peaks_") {
+            return prominence.parse::<f64>()
+                .map(HistReduce::Peaks)
+                .map_err(|_| "Invalid prominence for peaks_X".to_owned());
+        }
+        match lower.as_str() {
             "indexmax" | "index_max" => Ok(HistReduce::IndexMax),
             "val_max" | "valmax" => Ok(HistReduce::ValMax),
             "median" => Ok(HistReduce::Median),
-            _ => Err("Invalid HistReduce requested")
+            "duration" => Ok(HistReduce::Duration),
+            "auc" => Ok(HistReduce::Auc),
+            "half_rise" | "halfrise" => Ok(HistReduce::HalfRise),
+            "half_fall" | "halffall" => Ok(HistReduce::HalfFall),
+            "fwhm" => Ok(HistReduce::Fwhm),
+            "peaks" => Ok(HistReduce::Peaks(0.0)),
+            _ => {
+                s.parse::<Expression>()
+                    .map(HistReduce::Expression)
+                    .map_err(|e| format!("Invalid HistReduce requested: {}", e))
+            }
             }
     }
 }
 
+impl fmt::Display for HistReduce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistReduce::Peaks(prominence) => write!(f, "Peaks{}", prominence),
+            HistReduce::Expression(expr) => write!(f, "{}", expr.file_name()),
+            _ => write!(f, "{:?}", self)
+        }
+    }
+}
+
 
 fn max_val(arr: &[f64]) -> f64
 {
@@ -50,21 +132,59 @@ fn max_index(arr: &[f64]) -> usize
     max_helper(arr).0
 }
 
-fn max_helper(arr: &[f64]) -> (usize, f64)
+/// crossing of half the maximum
+fn half_max(flank: Flank) -> Crossing
 {
-    let mut max_index = 0;
-    let mut max_val = f64::NEG_INFINITY;
-    for (i, val) in arr.iter().copied().enumerate()
-    {
-        if val > max_val
+    Crossing{
+        flank,
+        threshold: Threshold::Relative(0.5)
+    }
+}
+
+/// Counts the local maxima whose prominence is at least `prominence`.
+/// The prominence is the height above the higher one of the two minima
+/// between the maximum and the next higher point to the left and right.
+/// Plateaus count as one maximum, maxima at the borders are not counted
+fn count_peaks(arr: &[f64], prominence: f64) -> usize
+{
+    let len = arr.len();
+    let mut count = 0;
+    let mut i = 0;
+    while i < len {
+        let peak = arr[i];
+        let mut j = i + 1;
+        while j < len && arr[j] == peak {
+            j += 1;
+        }
+        if i > 0 && j < len && arr[i - 1] < peak && arr[j] < peak
         {
-            max_val = val;
-            max_index = i;
+            let left_base = peak_base(arr[..i].iter().rev(), peak);
+            let right_base = peak_base(arr[j..].iter(), peak);
+            if peak - left_base.max(right_base) >= prominence {
+                count += 1;
+            }
+        }
+        i = j;
+    }
+    count
+}
+
+/// Minimum between the peak and the first point that is strictly higher than the peak,
+/// or the border. Points as high as the peak do not end the search
+fn peak_base<'a, I>(side: I, peak: f64) -> f64
+where I: Iterator<Item=&'a f64>
+{
+    let mut base = peak;
+    for &v in side {
+        if v > peak {
+            break;
         }
+        base = base.min(v);
     }
-    (max_index, max_val)
+    base
 }
 
+/// Reduced curves, grouped by energy bin
 #[derive(Clone)]
 pub struct Histogram{
     hist: Vec<Vec<f64>>
@@ -94,7 +214,6 @@ impl Histogram{
         self.hist
     }
 
-    #[allow(dead_code)]
     pub fn append(&mut self, mut other: Histogram)
     {
         match self.hist.len().cmp(&other.hist.len())
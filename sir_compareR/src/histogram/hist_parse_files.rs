@@ -1,10 +1,9 @@
-use crate::HistogramOpts;
-use lzma::LzmaReader;
-use flate2::read::*;
+use crate::parse_cmd::HistogramOpts;
+use crate::input::open_input;
 use std::io::*;
-use std::fs::*;
 use crate::histogram::*;
 use crate::parse_files::parse_helper;
+use crate::energy::EnergyAxis;
 use std::result::Result;
 use std::path::Path;
 
@@ -14,10 +13,11 @@ fn parse_and_group_naive<R, F>
     every: usize, 
     data: &mut Histogram,
     index_func: F,
-    reduce: HistReduce,
+    reduce: &HistReduce,
+    skipped: &mut usize
 )
 where
-    F: Fn(usize) -> usize,
+    F: Fn(usize) -> Option<usize>,
     R: Read,
 {
     let buf_reader = BufReader::new(reader);
@@ -37,31 +37,46 @@ where
                 let energy = it.next().unwrap();
                 
                 let energy = energy.parse::<usize>().unwrap();
+                let index = match index_func(energy) {
+                    Some(index) => index,
+                    None => return
+                };
+                let extinction_index = it.next()
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap();
                 
                 let vec: Vec<f64> = parse_helper(slice);
-                let res = reduce.reduce(&vec);
-
-                // append to correct bin
-                data.push(index_func(energy), res);
+                match reduce.reduce(&vec, energy, extinction_index) {
+                    // append to correct bin
+                    Some(res) => data.push(index, res),
+                    None => *skipped += 1
+                }
             }
         );
 }
 
+/// Reduces the curves of all files and groups them by energy
 pub fn parse_and_group_all_files(opts: HistogramOpts) -> Histogram
 {
     let mut hist_data = Histogram::new(opts.bins);
-    let index = |energy| (energy - 1) / opts.bin_size;
+    let index = |energy| opts.energy.index(energy);
     let files: Vec<_> = glob::glob(&opts.files)
         .unwrap()
         .filter_map(Result::ok)
         .collect();
     
+    let mut skipped = 0;
     files.iter()
         .for_each(|entry|
             {
-                parse_and_group_file(entry, opts.every, &mut hist_data, index, opts.hist_reduce);
+                parse_and_group_file(entry, opts.every, &mut hist_data, index, &opts.hist_reduce, &mut skipped);
             }
         );
+    EnergyAxis::report_out_of_range();
+    if skipped > 0 {
+        println!("{} curves skipped, {} is not defined for them", skipped, opts.hist_reduce);
+    }
     hist_data
 }
 
@@ -73,30 +88,13 @@ pub fn parse_and_group_file<P, F>
     every: usize,
     data: &mut Histogram,
     index_func: F,
-    hist_reduce: HistReduce
+    hist_reduce: &HistReduce,
+    skipped: &mut usize
 )
 where P: AsRef<Path>,
-    F: Fn(usize) -> usize,
+    F: Fn(usize) -> Option<usize>,
 {
-    let ending = filename.as_ref()
-        .extension()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let file = File::open(&filename).unwrap();
-
-    match ending {
-        "gz" => {
-            let decoder = GzDecoder::new(file);
-            parse_and_group_naive(decoder, every, data, index_func, hist_reduce)
-        },
-        "xz" => {
-            let decoder = LzmaReader::new_decompressor(file).unwrap();
-            parse_and_group_naive(decoder, every, data, index_func, hist_reduce)
-        },
-        _ => {
-            parse_and_group_naive(file, every, data, index_func, hist_reduce)
-        }
-    }
+    let reader = open_input(filename);
+    parse_and_group_naive(reader, every, data, index_func, hist_reduce, skipped)
 
 }
\ No newline at end of file
@@ -0,0 +1,167 @@
+use lzma::LzmaReader;
+use flate2::read::*;
+use std::io::*;
+use std::fs::*;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use lazy_static::*;
+
+lazy_static! {
+    /// decompressed file content, only used if caching is enabled
+    static ref INPUT_CACHE: RwLock<Option<InputCache>> = RwLock::new(None);
+}
+
+struct InputCache{
+    files: HashMap<PathBuf, SharedBytes>,
+    /// total size of the cached files
+    bytes: usize,
+    /// files are only cached while `bytes` stays below this limit
+    limit: usize
+}
+
+#[derive(Clone)]
+pub struct SharedBytes(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedBytes{
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+/// Keep the decompressed content of opened files in memory,
+/// such that later calls of `open_input` do not need to read and decompress it again.
+/// At most `limit` bytes are kept, files that do not fit anymore are not cached
+pub fn enable_cache(limit: usize)
+{
+    let mut lock = INPUT_CACHE.write().unwrap();
+    if lock.is_none() {
+        *lock = Some(
+            InputCache{
+                files: HashMap::new(),
+                bytes: 0,
+                limit
+            }
+        );
+    }
+}
+
+/// Disable caching and free the memory
+pub fn disable_cache()
+{
+    let mut lock = INPUT_CACHE.write().unwrap();
+    *lock = None;
+}
+
+/// Reader for plain, gz and xz compressed files, chosen by file extension
+pub enum InputReader{
+    Plain(File),
+    Gz(GzDecoder<File>),
+    Xz(LzmaReader<File>),
+    Cached(Cursor<SharedBytes>)
+}
+
+impl Read for InputReader{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Self::Plain(r) => r.read(buf),
+            Self::Gz(r) => r.read(buf),
+            Self::Xz(r) => r.read(buf),
+            Self::Cached(r) => r.read(buf)
+        }
+    }
+}
+
+fn open_uncached<P>(filename: P) -> Result<InputReader>
+where P: AsRef<Path>
+{
+    let file = File::open(&filename)?;
+    let ending = filename.as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+
+    let reader = match ending {
+        "gz" => InputReader::Gz(GzDecoder::new(file)),
+        "xz" => InputReader::Xz(
+            LzmaReader::new_decompressor(file)
+                .map_err(Error::other)?
+        ),
+        _ => InputReader::Plain(file)
+    };
+    Ok(reader)
+}
+
+/// Opens the file and decompresses it, if it ends with "gz" or "xz".
+/// Uses the cache, if enabled.
+/// Panics, if the file cannot be opened, see [try_open_input]
+pub fn open_input<P>(filename: P) -> InputReader
+where P: AsRef<Path>
+{
+    try_open_input(filename).unwrap()
+}
+
+/// Like [open_input], but returns the error, if the file cannot be opened or read.
+/// A file is only read into memory, if it still fits into the cache
+pub fn try_open_input<P>(filename: P) -> Result<InputReader>
+where P: AsRef<Path>
+{
+    let remaining = {
+        let lock = INPUT_CACHE.read().unwrap();
+        match lock.as_ref() {
+            None => return open_uncached(filename),
+            Some(cache) => {
+                if let Some(bytes) = cache.files.get(filename.as_ref()) {
+                    return Ok(InputReader::Cached(Cursor::new(bytes.clone())));
+                }
+                cache.limit.saturating_sub(cache.bytes)
+            }
+        }
+    };
+    if remaining == 0 {
+        return open_uncached(filename);
+    }
+
+    let mut reader = open_uncached(&filename)?;
+    if let InputReader::Plain(file) = &reader {
+        if file.metadata()?.len() > remaining as u64 {
+            return Ok(reader);
+        }
+    }
+    // the decompressed size is unknown, read at most one byte more than fits
+    let mut content = Vec::new();
+    reader.by_ref()
+        .take(remaining as u64 + 1)
+        .read_to_end(&mut content)?;
+    if content.len() > remaining {
+        return open_uncached(filename);
+    }
+    let bytes = SharedBytes(Arc::new(content));
+
+    let mut lock = INPUT_CACHE.write().unwrap();
+    if let Some(cache) = lock.as_mut() {
+        // another thread might have cached the file in the meantime
+        if let Some(cached) = cache.files.get(filename.as_ref()) {
+            return Ok(InputReader::Cached(Cursor::new(cached.clone())));
+        }
+        let len = bytes.as_ref().len();
+        if cache.bytes + len <= cache.limit {
+            cache.bytes += len;
+            cache.files.insert(filename.as_ref().to_path_buf(), bytes.clone());
+        }
+    }
+    Ok(InputReader::Cached(Cursor::new(bytes)))
+}
+
+/// path without the "gz" or "xz" extension, e.g., to name output files
+pub fn strip_compression(path: &Path) -> PathBuf
+{
+    let compressed = path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ending| matches!(ending, "gz" | "xz"));
+    if compressed {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    }
+}
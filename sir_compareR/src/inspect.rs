@@ -0,0 +1,388 @@
+use crate::parse_cmd::InspectOpts;
+use crate::header::*;
+use crate::input::open_input;
+use std::io::*;
+use std::path::Path;
+use std::result::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use indicatif::*;
+use rayon::prelude::*;
+use serde::Serialize;
+
+/// Number of header comment lines that are stored per file
+const MAX_HEADER_LINES: usize = 50;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport{
+    pub path: String,
+    /// number of data lines (no comments, no empty lines)
+    pub lines: usize,
+    pub comment_lines: usize,
+    pub malformed_lines: usize,
+    pub unfinished: usize,
+    /// N as found in the header comments, if any
+    pub n: Option<usize>,
+    pub energy_min: Option<usize>,
+    pub energy_max: Option<usize>,
+    pub curve_len_min: Option<usize>,
+    pub curve_len_max: Option<usize>,
+    /// comment lines before the first data line
+    pub header: Vec<String>,
+    /// parameters parsed from the header
+    pub params: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BinEntry{
+    pub left: usize,
+    pub right: usize,
+    pub count: usize
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary{
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+    pub mean: f64,
+    pub hist: Vec<BinEntry>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InspectReport{
+    pub pattern: String,
+    pub file_count: usize,
+    pub total_lines: usize,
+    pub malformed_lines: usize,
+    pub unfinished: usize,
+    pub energy: Summary,
+    pub curve_len: Summary,
+    /// distribution of the extinction index, unfinished trajectories are excluded
+    pub extinction_index: Summary,
+    /// all distinct N found in the headers
+    pub n_values: Vec<usize>,
+    /// files without N in the header
+    pub n_missing: usize,
+    /// true if all files have the same N in their header
+    pub n_agree: bool,
+    /// header parameters that differ between files, without e.g. the seed
+    pub param_mismatches: BTreeMap<String, BTreeSet<String>>,
+    pub files: Vec<FileReport>
+}
+
+/// Counts occurrences of values, can be merged
+#[derive(Debug, Clone, Default)]
+struct Counter{
+    counts: BTreeMap<usize, usize>
+}
+
+impl Counter{
+    fn count(&mut self, val: usize)
+    {
+        *self.counts.entry(val).or_insert(0) += 1;
+    }
+
+    fn merge(&mut self, other: &Self)
+    {
+        for (&val, &count) in other.counts.iter()
+        {
+            *self.counts.entry(val).or_insert(0) += count;
+        }
+    }
+
+    fn min(&self) -> Option<usize>
+    {
+        self.counts.keys().next().copied()
+    }
+
+    fn max(&self) -> Option<usize>
+    {
+        self.counts.keys().next_back().copied()
+    }
+
+    fn summary(&self, bins: usize) -> Summary
+    {
+        let total: usize = self.counts.values().sum();
+        let sum: f64 = self.counts
+            .iter()
+            .map(|(&val, &count)| val as f64 * count as f64)
+            .sum();
+        let mean = sum / total as f64;
+
+        let hist = match (self.min(), self.max())
+        {
+            (Some(min), Some(max)) => {
+                // right border is exclusive
+                let width = ((max - min + 1) as f64 / bins as f64).ceil() as usize;
+                let width = width.max(1);
+                let mut hist: Vec<_> = (0..)
+                    .map(|i| min + i * width)
+                    .take_while(|&left| left <= max)
+                    .map(|left| BinEntry{left, right: left + width, count: 0})
+                    .collect();
+                for (&val, &count) in self.counts.iter()
+                {
+                    hist[(val - min) / width].count += count;
+                }
+                hist
+            },
+            _ => Vec::new()
+        };
+
+        Summary{
+            min: self.min(),
+            max: self.max(),
+            mean,
+            hist
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Inspector{
+    lines: usize,
+    comment_lines: usize,
+    malformed_lines: usize,
+    unfinished: usize,
+    header: Vec<String>,
+    energy: Counter,
+    curve_len: Counter,
+    extinction_index: Counter
+}
+
+impl Inspector{
+    fn merge(&mut self, other: &Self)
+    {
+        self.lines += other.lines;
+        self.comment_lines += other.comment_lines;
+        self.malformed_lines += other.malformed_lines;
+        self.unfinished += other.unfinished;
+        self.energy.merge(&other.energy);
+        self.curve_len.merge(&other.curve_len);
+        self.extinction_index.merge(&other.extinction_index);
+    }
+
+    fn inspect_line(&mut self, line: &str)
+    {
+        let mut it = line.split_whitespace();
+        let energy = it.next()
+            .and_then(|e| e.parse::<usize>().ok());
+        let extinction_index = it.next()
+            .and_then(|e| e.parse::<usize>().ok());
+        let (energy, extinction_index) = match (energy, extinction_index)
+        {
+            (Some(e), Some(i)) => (e, i),
+            _ => {
+                self.malformed_lines += 1;
+                return;
+            }
+        };
+        self.lines += 1;
+        self.energy.count(energy);
+        self.curve_len.count(it.count());
+        if extinction_index == usize::MAX {
+            self.unfinished += 1;
+        } else {
+            self.extinction_index.count(extinction_index);
+        }
+    }
+
+    fn into_file_report(self, path: &Path) -> FileReport
+    {
+        let params = HeaderParams::from_lines(self.header.iter());
+        FileReport{
+            path: path.display().to_string(),
+            lines: self.lines,
+            comment_lines: self.comment_lines,
+            malformed_lines: self.malformed_lines,
+            unfinished: self.unfinished,
+            n: params.n(),
+            energy_min: self.energy.min(),
+            energy_max: self.energy.max(),
+            curve_len_min: self.curve_len.min(),
+            curve_len_max: self.curve_len.max(),
+            header: self.header,
+            params: params.params().clone()
+        }
+    }
+}
+
+fn inspect_reader<R: Read>(reader: R) -> Inspector
+{
+    let buf_reader = BufReader::new(reader);
+    let mut inspector = Inspector::default();
+
+    for line in buf_reader.lines()
+    {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                eprintln!("WARNING: stopped reading due to {}", error);
+                inspector.malformed_lines += 1;
+                break;
+            }
+        };
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            inspector.comment_lines += 1;
+            if inspector.lines == 0 && inspector.malformed_lines == 0
+                && inspector.header.len() < MAX_HEADER_LINES
+            {
+                inspector.header.push(trimmed.to_owned());
+            }
+            continue;
+        }
+        inspector.inspect_line(trimmed);
+    }
+    inspector
+}
+
+fn inspect_file<P>(filename: P) -> Inspector
+where P: AsRef<Path>
+{
+    let reader = open_input(filename);
+    inspect_reader(reader)
+}
+
+pub fn inspect_all_files(opts: &InspectOpts) -> InspectReport
+{
+    let files: Vec<_> = glob::glob(&opts.files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+
+    let inspectors: Vec<_> = files.par_iter()
+        .progress()
+        .map(inspect_file)
+        .collect();
+
+    let mut total = Inspector::default();
+    let mut file_reports = Vec::with_capacity(inspectors.len());
+    let mut headers = Vec::with_capacity(inspectors.len());
+    for (inspector, path) in inspectors.into_iter().zip(files.iter())
+    {
+        total.merge(&inspector);
+        headers.push((path.clone(), HeaderParams::from_lines(inspector.header.iter())));
+        file_reports.push(inspector.into_file_report(path));
+    }
+
+    let param_mismatches: BTreeMap<_, _> = combine_params(&headers)
+        .err()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|m| (m.key, m.values))
+        .collect();
+
+    let n_values: BTreeSet<_> = file_reports.iter()
+        .filter_map(|report| report.n)
+        .collect();
+    let n_missing = file_reports.iter()
+        .filter(|report| report.n.is_none())
+        .count();
+
+    InspectReport{
+        pattern: opts.files.clone(),
+        file_count: file_reports.len(),
+        total_lines: total.lines,
+        malformed_lines: total.malformed_lines,
+        unfinished: total.unfinished,
+        energy: total.energy.summary(opts.bins),
+        curve_len: total.curve_len.summary(opts.bins),
+        extinction_index: total.extinction_index.summary(opts.bins),
+        n_agree: n_values.len() <= 1 && n_missing == 0,
+        n_values: n_values.into_iter().collect(),
+        n_missing,
+        param_mismatches,
+        files: file_reports
+    }
+}
+
+fn write_summary<W: Write>(mut writer: W, name: &str, summary: &Summary) -> std::io::Result<()>
+{
+    writeln!(
+        writer,
+        "{}: min {:?} max {:?} mean {}",
+        name,
+        summary.min,
+        summary.max,
+        summary.mean
+    )?;
+    let max_count = summary.hist
+        .iter()
+        .map(|entry| entry.count)
+        .max()
+        .unwrap_or(0);
+    for entry in summary.hist.iter()
+    {
+        let stars = (40 * entry.count)
+            .checked_div(max_count)
+            .unwrap_or(0);
+        writeln!(
+            writer,
+            "  [{:>8}, {:>8}) {:>10} {}",
+            entry.left,
+            entry.right,
+            entry.count,
+            "*".repeat(stars)
+        )?;
+    }
+    Ok(())
+}
+
+impl InspectReport{
+    pub fn write_human<W: Write>(&self, mut writer: W) -> std::io::Result<()>
+    {
+        writeln!(writer, "pattern: {}", self.pattern)?;
+        writeln!(writer, "files: {}", self.file_count)?;
+        for file in self.files.iter()
+        {
+            writeln!(
+                writer,
+                "  {} lines: {} comments: {} malformed: {} unfinished: {} N: {:?} energy: {:?}..{:?} curve_len: {:?}..{:?}",
+                file.path,
+                file.lines,
+                file.comment_lines,
+                file.malformed_lines,
+                file.unfinished,
+                file.n,
+                file.energy_min,
+                file.energy_max,
+                file.curve_len_min,
+                file.curve_len_max
+            )?;
+        }
+        writeln!(writer, "total lines: {}", self.total_lines)?;
+        writeln!(writer, "malformed lines: {}", self.malformed_lines)?;
+        writeln!(writer, "UNFINISHED COUNT: {}", self.unfinished)?;
+        write_summary(&mut writer, "energy", &self.energy)?;
+        write_summary(&mut writer, "curve length", &self.curve_len)?;
+        write_summary(&mut writer, "extinction index", &self.extinction_index)?;
+
+        if let Some(first) = self.files.first()
+        {
+            writeln!(writer, "header of {}:", first.path)?;
+            for line in first.header.iter()
+            {
+                writeln!(writer, "  {}", line)?;
+            }
+        }
+
+        for (key, values) in self.param_mismatches.iter()
+        {
+            writeln!(writer, "WARNING: parameter {} does not agree! Found {:?}", key, values)?;
+        }
+
+        if self.n_agree {
+            writeln!(writer, "N: {:?}", self.n_values.first())
+        } else {
+            writeln!(
+                writer,
+                "WARNING: N does not agree! Found {:?}, {} files without N",
+                self.n_values,
+                self.n_missing
+            )
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::parse_cmd::*;
+use crate::{input, stats, commands};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use std::collections::BTreeMap;
+use std::fs::*;
+use std::io::*;
+use std::result::Result;
+
+/// Content of a job file, e.g.
+/// ```toml
+/// share_input = true
+/// share_input_limit_mb = 2048
+///
+/// [[analysis]]
+/// subcommand = "heatmap2"
+/// files = "*.mes.xz"
+/// [analysis.options]
+/// every = 1
+/// heatmap = "u 100 0 1000"
+/// [analysis.grid]
+/// fun = ["index_max", "last"]
+/// bins = [20, 40]
+/// ```
+/// Arrays in `grid` span a parameter grid, i.e.,
+/// every combination of the values is analyzed.
+/// Arrays in `options` are passed as the values of one option,
+/// e.g. `percent = [0.1, 0.5, 0.9]` becomes `--percent 0.1 0.5 0.9`
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobFile{
+    /// name of the manifest to create
+    pub manifest: Option<String>,
+    /// Keep decompressed input in memory, while analyses
+    /// with the same file pattern are running.
+    /// This needs as much memory as the decompressed files, see `share_input_limit_mb`
+    #[serde(default)]
+    pub share_input: bool,
+    /// Upper limit of the memory used to share the input in MB.
+    /// Files that do not fit are read and decompressed again for each analysis
+    #[serde(default = "default_share_input_limit")]
+    pub share_input_limit_mb: usize,
+    pub analysis: Vec<Analysis>
+}
+
+fn default_share_input_limit() -> usize
+{
+    4096
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Analysis{
+    pub subcommand: String,
+    pub files: String,
+    #[serde(default)]
+    pub options: BTreeMap<String, toml::Value>,
+    /// options with one analysis for each value
+    #[serde(default)]
+    pub grid: BTreeMap<String, toml::Value>
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestEntry{
+    pub args: Vec<String>,
+    pub files: String,
+    pub outputs: Vec<String>
+}
+
+fn value_to_arg(key: &str, value: &toml::Value) -> Result<String, String>
+{
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        _ => Err(format!("invalid value for option {}: {}", key, value))
+    }
+}
+
+fn option_name(key: &str) -> String
+{
+    if key.chars().count() == 1 {
+        format!("-{}", key)
+    } else {
+        format!("--{}", key.replace('_', "-"))
+    }
+}
+
+/// Appends option `key` with `value` to `args`.
+/// Flags are only added if true, arrays are the values of one option
+fn push_option(args: &mut Vec<String>, key: &str, value: &toml::Value) -> Result<(), String>
+{
+    let name = option_name(key);
+    match value {
+        toml::Value::Boolean(flag) => {
+            if *flag {
+                args.push(name);
+            }
+        },
+        toml::Value::Array(values) => {
+            args.push(name);
+            for value in values
+            {
+                args.push(value_to_arg(key, value)?);
+            }
+        },
+        _ => {
+            args.push(name);
+            args.push(value_to_arg(key, value)?);
+        }
+    }
+    Ok(())
+}
+
+impl Analysis{
+    /// Argument lists, one for each point of the parameter grid.
+    /// Error for nested jobs, invalid option values and options that are
+    /// in `options` and `grid`
+    pub fn expand(&self) -> Result<Vec<Vec<String>>, String>
+    {
+        let subcommand = self.subcommand
+            .to_lowercase()
+            .replace('_', "-");
+        if subcommand == "job" {
+            return Err("jobs cannot be nested".to_owned());
+        }
+        if let Some(key) = self.grid.keys().find(|key| self.options.contains_key(*key)) {
+            return Err(format!("option {} is in options and grid", key));
+        }
+        let mut args = vec![
+            env!("CARGO_PKG_NAME").to_owned(),
+            subcommand,
+            "--files".to_owned(),
+            self.files.clone()
+        ];
+        for (key, value) in self.options.iter()
+        {
+            push_option(&mut args, key, value)?;
+        }
+        let mut arg_lists = vec![args];
+
+        for (key, value) in self.grid.iter()
+        {
+            let values = match value {
+                toml::Value::Array(values) => values.as_slice(),
+                _ => std::slice::from_ref(value)
+            };
+            let mut expanded = Vec::with_capacity(arg_lists.len() * values.len());
+            for args in arg_lists.iter()
+            {
+                for value in values
+                {
+                    let mut args = args.clone();
+                    push_option(&mut args, key, value)?;
+                    expanded.push(args);
+                }
+            }
+            arg_lists = expanded;
+        }
+        Ok(arg_lists)
+    }
+}
+
+pub fn run_jobs(opts: JobOpts) -> Result<Vec<String>, String>
+{
+    let content = std::fs::read_to_string(&opts.file)
+        .map_err(|error| format!("unable to read job file {}: {}", &opts.file, error))?;
+    let job_file: JobFile = toml::from_str(&content)
+        .map_err(|error| format!("invalid job file {}: {}", &opts.file, error))?;
+
+    // check all arguments before starting the first analysis
+    let mut jobs = Vec::new();
+    for analysis in job_file.analysis.iter()
+    {
+        for args in analysis.expand()?
+        {
+            match Opt::from_iter_safe(&args) {
+                Ok(_) => jobs.push((analysis.files.clone(), args)),
+                Err(error) => {
+                    return Err(format!("invalid analysis {:?}\n{}", args, error.message));
+                }
+            }
+        }
+    }
+
+    if job_file.share_input {
+        // run analyses of the same files after one another
+        jobs.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+
+    println!("{} analyses", jobs.len());
+    if opts.dry_run {
+        for (_, args) in jobs.iter()
+        {
+            println!("{}", args.join(" "));
+        }
+        return Ok(Vec::new());
+    }
+
+    let name = job_file.manifest
+        .unwrap_or_else(|| format!("{}.manifest.json", &opts.file));
+    let mut manifest = Vec::with_capacity(jobs.len());
+    let mut current_files: Option<String> = None;
+    for (files, args) in jobs
+    {
+        if job_file.share_input && current_files.as_ref() != Some(&files) {
+            input::disable_cache();
+            input::enable_cache(job_file.share_input_limit_mb * 1024 * 1024);
+            current_files = Some(files.clone());
+        }
+        println!("running: {}", args.join(" "));
+        stats::set_cmd_args(Some(args.join(" ")));
+        commands::reset_counters();
+        let options = Opt::from_iter(&args);
+        let outputs = match crate::run(options) {
+            Ok(outputs) => outputs,
+            Err(error) => {
+                stats::set_cmd_args(None);
+                input::disable_cache();
+                // the outputs of the finished analyses are still listed
+                let error = format!("{}: {}", args.join(" "), error);
+                return match write_manifest(&name, &manifest) {
+                    Ok(()) => Err(format!("{}\nmanifest of the {} finished analyses: {}", error, manifest.len(), name)),
+                    Err(manifest_error) => Err(format!("{}\n{}", error, manifest_error))
+                };
+            }
+        };
+        commands::warn_normed();
+        manifest.push(
+            ManifestEntry{
+                args,
+                files,
+                outputs
+            }
+        );
+    }
+    stats::set_cmd_args(None);
+    input::disable_cache();
+
+    write_manifest(&name, &manifest)?;
+    println!("generated: {}", &name);
+    Ok(vec![name])
+}
+
+fn write_manifest(name: &str, manifest: &[ManifestEntry]) -> Result<(), String>
+{
+    let file = File::create(name)
+        .map_err(|error| format!("unable to create manifest {}: {}", name, error))?;
+    let writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(writer, manifest)
+        .map_err(|error| format!("unable to write manifest {}: {}", name, error))
+}
@@ -0,0 +1,35 @@
+//! Analysis of the trajectories generated by `sir_exec`.
+//!
+//! The data files contain one trajectory per line:
+//! the energy, the extinction index and the values of the curve.
+//! This crate provides
+//! * parsers for plain, gz and xz compressed files, see [input], [parse_files],
+//!   [heatmap2::parse_and_count_all_files] and [hist_analyser::parse_and_count_all_files]
+//! * binning of the trajectories by energy, see [energy::EnergyAxis], [stats::Data] and [histogram::Histogram]
+//! * feature extraction from the curves, see [heatmap2::FunctionChooser], [histogram::HistReduce]
+//!   and [expression::Expression]
+//! * comparison of curves, see [analyse::compare_curves] and [stats::reduce]
+//!
+//! The command line interface of `sir_compare_r` is a thin layer on top of [commands::run].
+pub mod parse_files;
+pub mod parse_cmd;
+pub mod analyse;
+pub mod stats;
+pub mod histogram;
+pub mod heatmap2;
+pub mod hist_analyser;
+pub mod heatmap_generic;
+pub mod inspect;
+pub mod header;
+pub mod input;
+pub mod job;
+pub mod multi;
+pub mod energy;
+pub mod expression;
+pub mod crossing;
+pub mod auto_range;
+pub mod export;
+pub mod commands;
+
+pub use parse_cmd::*;
+pub use commands::run;
@@ -0,0 +1,297 @@
+use crate::parse_cmd::MultiOpts;
+use crate::input::open_input;
+use crate::heatmap2::*;
+use crate::hist_analyser::HistSampler;
+use crate::histogram::{Histogram, HistReduce, histogramm_parallel};
+use crate::commands::*;
+use crate::energy::EnergyAxis;
+use crate::export::Export;
+use std::io::*;
+use std::fmt;
+use std::str::FromStr;
+use std::result::Result;
+use std::path::{Path, PathBuf};
+use sampling::*;
+use either::*;
+use indicatif::*;
+use rayon::prelude::*;
+use std::sync::atomic::Ordering;
+
+/// One feature together with the output that should be created from it
+#[derive(Debug, Clone)]
+pub enum MultiTask{
+    /// heatmap of energy vs. feature, see Heatmap2 subcommand
+    Heatmap(FunctionChooser, HeatmapBuilder),
+    /// percentile of the feature for each energy bin, see Percent subcommand
+    Percent(FunctionChooser, f64),
+    /// mean of the reduced curves for each energy bin, see Histogram subcommand
+    Histogram(HistReduce)
+}
+
+impl MultiTask{
+    /// Has to be called before evaluating expressions containing `n`
+    pub fn bind_n(&mut self, n: usize)
+    {
+        match self {
+            Self::Heatmap(fun, _) | Self::Percent(fun, _) => fun.bind_n(n),
+            Self::Histogram(reduce) => reduce.bind_n(n)
+        }
+    }
+}
+
+impl FromStr for MultiTask {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.splitn(3, ':');
+        let feature = iter.next()
+            .ok_or("Task: feature missing")?
+            .trim();
+        let kind = iter.next()
+            .ok_or("Task: output kind missing. Use e.g. 'index_max:heatmap:u 100 0 1000', 'val_max:percent:0.9' or 'last:histogram'")?
+            .trim()
+            .to_lowercase();
+        let arg = iter.next();
+        let task = match kind.as_str() {
+            "heatmap" | "h" => {
+                let builder = arg.ok_or("Task: heatmap needs e.g. 'u 100 0 100'")?
+                    .parse::<HeatmapBuilder>()?;
+                if builder.is_auto() {
+                    return Err("Task: auto range is not supported, all tasks are counted in a single pass".to_owned());
+                }
+                Self::Heatmap(feature.parse()?, builder)
+            },
+            "percent" | "p" => {
+                let p = arg.ok_or("Task: percent needs the percent value")?
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| "Task: unable to parse percent value")?;
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(format!("Task: percentile {} has to be in [0, 1]", p));
+                }
+                Self::Percent(feature.parse()?, p)
+            },
+            "histogram" | "hist" => {
+                if arg.is_some() {
+                    return Err("Task: histogram does not take any arguments".to_owned());
+                }
+                let reduce = feature.parse::<HistReduce>()
+                    .map_err(|error| format!("Task: {}", error))?;
+                Self::Histogram(reduce)
+            },
+            _ => return Err("Task: unknown output kind, valid: heatmap, percent, histogram".to_owned())
+        };
+        Ok(task)
+    }
+}
+
+impl fmt::Display for MultiTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Heatmap(fun, builder) => write!(f, "{}_{}", fun, builder),
+            Self::Percent(fun, p) => write!(f, "{}_p{}", fun, p),
+            Self::Histogram(reduce) => write!(f, "{}_hist", reduce)
+        }
+    }
+}
+
+/// Collects the values of one task
+#[derive(Clone)]
+pub enum Accumulator{
+    Heatmap(EitherH),
+    Percent(HistSampler<f64, HistUsize>),
+    Histogram(Histogram)
+}
+
+impl Accumulator{
+    pub fn new(task: &MultiTask, energy: &EnergyAxis) -> Self
+    {
+        match task {
+            MultiTask::Heatmap(_, builder) => Self::Heatmap(builder.build(energy)),
+            MultiTask::Percent(..) => Self::Percent(HistSampler::new(energy.hist())),
+            MultiTask::Histogram(_) => Self::Histogram(Histogram::new(energy.bins))
+        }
+    }
+
+    /// Features of heatmaps with float axis are floats,
+    /// percent features are floats for `float`, `normed` and expressions, as for the Percent subcommand
+    fn count(&mut self, task: &MultiTask, curve: &CurveLine, opts: &MultiOpts)
+    {
+        match (self, task) {
+            (Self::Heatmap(Left(heatmap)), MultiTask::Heatmap(fun, _)) => {
+                parse_into_heatmap_f64(curve, heatmap, fun, opts.normed);
+            },
+            (Self::Heatmap(Right(heatmap)), MultiTask::Heatmap(fun, _)) => {
+                parse_into_heatmap_usize(curve, heatmap, fun, opts.normed);
+            },
+            (Self::Percent(sampler), MultiTask::Percent(fun, _)) => {
+                let val = if opts.float || opts.normed || fun.is_float() {
+                    f64_feature(curve, fun, opts.normed)
+                } else {
+                    usize_feature(curve, fun, opts.normed)
+                        .map(|val| val as f64)
+                };
+                if let Some(val) = val {
+                    sampler.count(curve.energy, val);
+                }
+            },
+            (Self::Histogram(hist), MultiTask::Histogram(reduce)) => {
+                if let Some(index) = opts.energy.index(curve.energy) {
+                    // usize::MAX marks unfinished curves, as in the files
+                    let extinction_index = curve.extinction_index.unwrap_or(usize::MAX);
+                    match reduce.reduce(&curve.values, curve.energy, extinction_index) {
+                        Some(val) => hist.push(index, val),
+                        None => {
+                            MISS_COUNTER.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            },
+            _ => unreachable!("accumulator does not match task")
+        }
+    }
+
+    fn combine(&mut self, other: Self)
+    {
+        match (self, other) {
+            (Self::Heatmap(Left(acc)), Self::Heatmap(Left(other))) => {
+                acc.combine(&other).unwrap();
+            },
+            (Self::Heatmap(Right(acc)), Self::Heatmap(Right(other))) => {
+                acc.combine(&other).unwrap();
+            },
+            (Self::Percent(acc), Self::Percent(other)) => {
+                acc.dirty_add(&other);
+            },
+            (Self::Histogram(acc), Self::Histogram(other)) => {
+                acc.append(other);
+            },
+            _ => unreachable!("accumulators do not match")
+        }
+    }
+}
+
+fn parse_and_count<R>
+(
+    reader: R,
+    opts: &MultiOpts,
+    accumulators: &mut [Accumulator]
+)
+where
+    R: Read,
+{
+    let buf_reader = BufReader::new(reader);
+
+    buf_reader.lines()
+        .map(|v| v.unwrap())
+        .filter(|line|
+            {
+                !line.trim_start().starts_with('#') // skip comments
+                && !line.is_empty()
+            }
+        ).step_by(opts.every)
+        // every curve is parsed only once for all tasks
+        .filter_map(|line| CurveLine::parse(line.trim()))
+        .for_each( |curve|
+            {
+                for (task, acc) in opts.tasks.iter().zip(accumulators.iter_mut())
+                {
+                    acc.count(task, &curve, opts);
+                }
+            }
+        );
+}
+
+fn parse_and_count_file<P>
+(
+    filename: P,
+    opts: &MultiOpts,
+    accumulators: &mut [Accumulator]
+)
+where P: AsRef<Path>
+{
+    let reader = open_input(filename);
+    parse_and_count(reader, opts, accumulators)
+}
+
+/// Fills the accumulators of all tasks in one pass over the data.
+/// Files are parsed in parallel
+pub fn parse_and_count_all_files(opts: &MultiOpts) -> (Vec<PathBuf>, Vec<Accumulator>)
+{
+    let files: Vec<_> = glob::glob(&opts.files)
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+
+    let mut accumulators: Vec<_> = opts.tasks
+        .iter()
+        .map(|task| Accumulator::new(task, &opts.energy))
+        .collect();
+
+    let per_file: Vec<_> = files.par_iter()
+        .progress()
+        .map(|entry|
+            {
+                let mut acc = accumulators.clone();
+                parse_and_count_file(entry, opts, &mut acc);
+                acc
+            }
+        ).collect();
+
+    EnergyAxis::report_out_of_range();
+    report_misses();
+    for other in per_file {
+        for (acc, other) in accumulators.iter_mut().zip(other)
+        {
+            acc.combine(other);
+        }
+    }
+    (files, accumulators)
+}
+
+/// Creates the output of all tasks
+pub fn write_multi(opts: MultiOpts) -> Result<Vec<String>, String>
+{
+    let (files, accumulators) = parse_and_count_all_files(&opts);
+
+    let mut outputs = Vec::with_capacity(opts.tasks.len());
+    for (task, acc) in opts.tasks.iter().zip(accumulators)
+    {
+        match (task, acc) {
+            (MultiTask::Heatmap(fun, builder), Accumulator::Heatmap(heatmap)) => {
+                let filename = opts.generate_filename(task, "h2.gp");
+                println!("creating: {}", &filename);
+                let mut export = Export::new(opts.generate_filename(task, "h2"), &opts.export);
+                export.info("feature", fun)
+                    .info("heatmap", builder);
+                write_heatmap2_gnuplot(
+                    &filename,
+                    &file_comments(&files),
+                    heatmap,
+                    fun.label(),
+                    opts.plot,
+                    &opts.params,
+                    &mut export
+                )?;
+                if opts.gnuplot_exec {
+                    exec_gnuplot(&filename);
+                }
+                outputs.push(filename);
+                outputs.extend(export.write(&opts.params));
+            },
+            (MultiTask::Percent(_, p), Accumulator::Percent(mut sampler)) => {
+                let res = sampler.percentiles(&[*p]);
+                let filename = opts.generate_filename(task, "percent");
+                write_percent_table(&filename, res, &[*p], None, &opts.params)?;
+                outputs.push(filename);
+            },
+            (MultiTask::Histogram(_), Accumulator::Histogram(hist)) => {
+                let hist = histogramm_parallel(hist, opts.j, !opts.no_p_bar, opts.error);
+                let filename = opts.generate_filename(task, "dat");
+                write_histogram_table(&filename, hist, &opts.error, &opts.params)?;
+                outputs.push(filename);
+            },
+            _ => unreachable!()
+        }
+    }
+    Ok(outputs)
+}
@@ -2,12 +2,17 @@ use structopt::StructOpt;
 use std::ops::Deref;
 use std::str::FromStr;
 use std::{convert::*, num::NonZeroUsize};
-use std::process::exit;
 use std::collections::*;
 use crate::parse_files::*;
 use crate::histogram::*;
 use crate::heatmap2::*;
-use crate::heatmap_generic::HistBuilder;
+use crate::hist_analyser::PercentBootstrap;
+use crate::heatmap_generic::{HistBuilder, WeightScale};
+use crate::header::*;
+use crate::energy::*;
+use crate::multi::MultiTask;
+use crate::export::ExportFormat;
+use crate::expression::{Expression, ColumnExpression};
 use sampling::heatmap::{GnuplotPalette, CubeHelixParameter};
 
 const COMPRESSION_SUFFIX: [&str; 2]= ["gz", "xz"];
@@ -27,7 +32,8 @@ pub enum Opt
         #[structopt(long,short)]
         n: usize,
 
-        /// actual number of nodes
+        /// actual number of nodes, only used in the names of the output files.
+        /// Default: N from the file headers
         #[structopt(long)]
         n_real: Option<usize>,
 
@@ -79,18 +85,19 @@ pub enum Opt
         #[structopt(long, default_value = "0")]
         mode: usize,
 
-        /// Do not subtract 1 from the energy value
-        #[structopt(long)]
-        no_subtract: bool,
+        /// Additionally export the matrices and axes, e.g. 'npz' or 'npy,csv'.
+        /// valid: npy, npz, csv. A JSON file with the metadata is written as well
+        #[structopt(long, use_delimiter = true)]
+        export: Vec<ExportFormat>,
 
-        /// Use this option when C=0 is allowed
-        #[structopt(long)]
-        c0: bool
+        #[structopt(flatten)]
+        energy: EnergyArgs
     },
     Histogram {
-        /// number of nodes
+        /// number of nodes.
+        /// Default: N from the file headers
         #[structopt(long,short)]
-        n: usize,
+        n: Option<usize>,
 
         /// number of samples
         #[structopt(long, short)]
@@ -117,14 +124,37 @@ pub enum Opt
         every: usize,
 
         /// What function to use
-        /// valid: indexmax, valmax
+        /// valid: indexmax, valmax, median, duration, auc,
+        /// half_rise, half_fall, fwhm,
+        /// peaks_x (number of local maxima with prominence of at least x)
+        /// or an expression, e.g. 'val_max / n' or 'extinction - index_max'
+        #[structopt(long)]
+        hist_reduce: HistReduce,
+
+        #[structopt(flatten)]
+        error: ErrorArgs,
+
+        /// additionally write count, mean, standard deviation, min, max 
+        /// and quantiles of each bin
         #[structopt(long)]
-        hist_reduce: HistReduce
+        bin_stats: bool,
+
+        /// quantiles used for bin_stats
+        #[structopt(long, use_delimiter = true, default_value = "0.05,0.25,0.5,0.75,0.95")]
+        quantiles: Vec<f64>,
+
+        /// additionally write all values of each bin, one per line
+        #[structopt(long)]
+        dump_samples: bool,
+
+        #[structopt(flatten)]
+        energy: EnergyArgs
     },
     Heatmap2 {
-        /// number of nodes
+        /// number of nodes.
+        /// Default: N from the file headers
         #[structopt(long,short)]
-        n: usize,
+        n: Option<usize>,
 
         /// number of bins for energy
         #[structopt(long)]
@@ -133,6 +163,8 @@ pub enum Opt
         /// For requesting the heatmap
         /// e.g. 'f 100 0 100' for float, 100 bins, left 0, right 100
         /// e.g. 'u 100 0 100' for usize, 100 bins, left 0, right 100
+        /// e.g. 'u 100 auto' or 'f 100 auto 0.01' for a range determined from the data
+        /// in a pre-pass, optionally clipping the given fraction of outliers at each end
         #[structopt(long)]
         heatmap: HeatmapBuilder,
 
@@ -166,40 +198,79 @@ pub enum Opt
         /// 'index_min' bzw. 'indexmin',
         /// 'val_min' bzw. 'valmin',
         /// 'lastindexnotzero' bzw. 'last_index_not_zero' bzw. 'last-index-not-zero' bzw. 'last',
-        /// 'x_to_y' where x and y are f64
+        /// 'x_to_y' where x and y are f64,
+        /// 'cross_rise_X' bzw. 'cross_fall_X' (interpolated time at which the curve crosses X times its maximum),
+        /// 'cross_rise_abs_X' bzw. 'cross_fall_abs_X' (absolute threshold X).
+        /// Curves for which the feature is undefined are skipped and counted,
+        /// or an expression over these features, e.g. 'val_max / n' or 'index_max - from(0.1)'
         #[structopt(long)]
         fun: FunctionChooser,
 
-        /// Use rgb color palett
-        #[structopt(long)]
-        rgb: bool,
+        /// Only count curves for which the condition is true,
+        /// e.g. 'val_max > 0.1 * n && extinction < 200' or '!(index_max < 10 || last > 300)'.
+        /// Same variables and functions as for expressions in 'fun'
+        #[structopt(long = "where")]
+        filter: Option<Expression>,
 
-        /// Use this option when C=0 is allowed
-        #[structopt(long)]
-        c0: bool
-        
+        #[structopt(flatten)]
+        plot: PlotArgs,
+
+        /// Additionally export the matrices and axes, e.g. 'npz' or 'npy,csv'.
+        /// valid: npy, npz, csv. A JSON file with the metadata is written as well
+        #[structopt(long, use_delimiter = true)]
+        export: Vec<ExportFormat>,
+
+        #[structopt(flatten)]
+        energy: EnergyArgs
     },
     GenericHeatmap
     {
         /// For requesting the heatmap
         /// e.g. 'f 100 0 100' for float, 100 bins, left 0, right 100
         /// e.g. 'i 100 0 100' for isize, 100 bins, left 0, right 100
+        /// e.g. 'f 100 auto 0.01' for a range determined from the data, clipping 1% at each end
         #[structopt(long)]
         hist_x: HistBuilder,
 
         /// For requesting the heatmap
         /// e.g. 'f 100 0 100' for float, 100 bins, left 0, right 100
         /// e.g. 'i 100 0 100' for isize, 100 bins, left 0, right 100
+        /// e.g. 'f 100 auto 0.01' for a range determined from the data, clipping 1% at each end
         #[structopt(long)]
         hist_y: HistBuilder,
 
-        /// Index of the x value. Default 0
-        #[structopt(long,short, default_value="0")]
-        x_index: usize,
+        /// x value: index of a column or an expression over columns,
+        /// e.g. '0', 'c3 / c0', 'log10(c2)' or 'c1 - c4'.
+        /// Columns can also be named as in the last '#' header line of the first file
+        #[structopt(long, short, default_value = "0", visible_alias = "x-index")]
+        x: ColumnExpression,
 
-        /// Index of the y value. Default 1
-        #[structopt(long,short, default_value="1")]
-        y_index: usize,
+        /// y value: index of a column or an expression over columns, see x
+        #[structopt(long, short, default_value = "1", visible_alias = "y-index")]
+        y: ColumnExpression,
+
+        /// Only count lines for which the condition over the columns is true,
+        /// e.g. 'c2 > 0.5 && c3 <= 100' or '!(infected == 0 || c1 > c2)'
+        #[structopt(long = "where")]
+        filter: Option<ColumnExpression>,
+
+        /// Weight of each line: index of a column or an expression over columns, see x.
+        /// Weights are accumulated per cell and the weighted heatmap is plotted
+        #[structopt(long)]
+        weight: Option<ColumnExpression>,
+
+        /// How the weight is given. valid: linear, ln, log10 (e.g. log-weights of reweighted data)
+        #[structopt(long, default_value = "linear")]
+        weight_scale: WeightScale,
+
+        /// number of threads to use, the files are parsed in parallel.
+        /// 0: one thread per core
+        #[structopt(short, default_value = "0")]
+        j: usize,
+
+        #[structopt(long)]
+        /// hide progress bar
+        no_p_bar: bool,
 
         /// filenames (globbing pattern)
         #[structopt(long, short)]
@@ -239,12 +310,155 @@ pub enum Opt
 
         /// Which palett to use. "r" for rgb, "h" for hsv and "c" for cubehelix. Use "c,r" for cubehelix reversed
         #[structopt(long, short, default_value="r")]
-        palett: GnuPalett
+        palett: GnuPalett,
+
+        /// Additionally export the matrices and axes, e.g. 'npz' or 'npy,csv'.
+        /// valid: npy, npz, csv. A JSON file with the metadata is written as well
+        #[structopt(long, use_delimiter = true)]
+        export: Vec<ExportFormat>
+    },
+    GenericHistogram
+    {
+        /// For requesting the histogram
+        /// e.g. 'f 100 0 100' for float, 100 bins, left 0, right 100
+        /// e.g. 'i 100 0 100' for isize, 100 bins, left 0, right 100
+        /// e.g. 'f 100 auto 0.01' for a range determined from the data, clipping 1% at each end
+        #[structopt(long)]
+        hist: HistBuilder,
+
+        /// value: index of a column or an expression over columns,
+        /// e.g. '0', 'c3 / c0', 'log10(c2)' or 'c1 - c4'.
+        /// Columns can also be named as in the last '#' header line of the first file
+        #[structopt(long, short, default_value = "0", visible_alias = "index")]
+        x: ColumnExpression,
+
+        /// Only count lines for which the condition over the columns is true,
+        /// e.g. 'c2 > 0.5 && c3 <= 100'
+        #[structopt(long = "where")]
+        filter: Option<ColumnExpression>,
+
+        /// filenames (globbing pattern)
+        #[structopt(long, short)]
+        files: String,
+
+        /// use every "every"th data point
+        #[structopt(long, short, default_value = "1")]
+        every: NonZeroUsize,
+
+        /// X label for the histogram
+        #[structopt(long)]
+        x_label: Option<String>,
+
+        /// Base name of the created files, i.e., name.dat and name.gp
+        #[structopt(long)]
+        name: String,
+
+        /// How the file generated by the gnuplot file will be called
+        #[structopt(long)]
+        gnuplot_output_name: Option<String>,
+
+        /// automatically call gnuplot to plot the resulting histogram
+        #[structopt(long, short)]
+        gnuplot: bool,
+
+        /// Do not output hist errors
+        #[structopt(long, short)]
+        supress_hist_error: bool,
+
+        /// Additionally export the histogram and the bin edges, e.g. 'npz' or 'npy,csv'.
+        /// valid: npy, npz, csv. A JSON file with the metadata is written as well
+        #[structopt(long, use_delimiter = true)]
+        export: Vec<ExportFormat>
+    },
+    Histogram3d
+    {
+        /// Histogram of the x axis
+        /// e.g. 'f 100 0 100' for float, 100 bins, left 0, right 100
+        /// e.g. 'i 100 0 100' for isize, 100 bins, left 0, right 100
+        /// e.g. 'f 100 auto 0.01' for a range determined from the data, clipping 1% at each end
+        #[structopt(long)]
+        hist_x: HistBuilder,
+
+        /// Histogram of the y axis, see hist-x
+        #[structopt(long)]
+        hist_y: HistBuilder,
+
+        /// Histogram of the z axis, see hist-x
+        #[structopt(long)]
+        hist_z: HistBuilder,
+
+        /// x value: index of a column or an expression over columns,
+        /// e.g. '0', 'c3 / c0', 'log10(c2)' or 'c1 - c4'.
+        /// Columns can also be named as in the last '#' header line of the first file
+        #[structopt(long, short, default_value = "0")]
+        x: ColumnExpression,
+
+        /// y value, see x
+        #[structopt(long, short, default_value = "1")]
+        y: ColumnExpression,
+
+        /// z value, see x
+        #[structopt(long, short, default_value = "2")]
+        z: ColumnExpression,
+
+        /// Only count lines for which the condition over the columns is true,
+        /// e.g. 'c2 > 0.5 && c3 <= 100'
+        #[structopt(long = "where")]
+        filter: Option<ColumnExpression>,
+
+        /// filenames (globbing pattern)
+        #[structopt(long, short)]
+        files: String,
+
+        /// use every "every"th data point
+        #[structopt(long, short, default_value = "1")]
+        every: NonZeroUsize,
+
+        /// X label
+        #[structopt(long)]
+        x_label: Option<String>,
+
+        /// Y label
+        #[structopt(long)]
+        y_label: Option<String>,
+
+        /// Z label
+        #[structopt(long)]
+        z_label: Option<String>,
+
+        /// Base name of the created files: name.grid.dat contains the full grid,
+        /// name_xy.gp, name_xz.gp and name_yz.gp plot the 2D marginals
+        /// and name_slices.gp plots the heatmap of x and y for each z bin
+        #[structopt(long)]
+        name: String,
+
+        /// automatically call gnuplot to plot the marginals and slices
+        #[structopt(long, short)]
+        gnuplot: bool,
+
+        /// Which palett to use. "r" for rgb, "h" for hsv and "c" for cubehelix. Use "c,r" for cubehelix reversed
+        #[structopt(long, short, default_value="r")]
+        palett: GnuPalett,
+
+        /// number of threads to use, the files are parsed in parallel.
+        /// 0: one thread per core
+        #[structopt(short, default_value = "0")]
+        j: usize,
+
+        #[structopt(long)]
+        /// hide progress bar
+        no_p_bar: bool,
+
+        /// Additionally export the grid, the marginals and the bin edges, e.g. 'npz' or 'npy,csv'.
+        /// valid: npy, npz, csv. A JSON file with the metadata is written as well
+        #[structopt(long, use_delimiter = true)]
+        export: Vec<ExportFormat>
     },
     Percent {
-        /// number of nodes
+        /// number of nodes.
+        /// Default: N from the file headers
         #[structopt(long,short)]
-        n: usize,
+        n: Option<usize>,
 
         /// number of bins for energy
         #[structopt(long)]
@@ -260,7 +474,11 @@ pub enum Opt
         /// 'index_min' bzw. 'indexmin',
         /// 'val_min' bzw. 'valmin',
         /// 'lastindexnotzero' bzw. 'last_index_not_zero' bzw. 'last-index-not-zero' bzw. 'last',
-        /// 'x_to_y' where x and y are f64
+        /// 'x_to_y' where x and y are f64,
+        /// 'cross_rise_X' bzw. 'cross_fall_X' (interpolated time at which the curve crosses X times its maximum),
+        /// 'cross_rise_abs_X' bzw. 'cross_fall_abs_X' (absolute threshold X).
+        /// Curves for which the feature is undefined are skipped and counted,
+        /// or an expression over these features, e.g. 'val_max / n' or 'index_max - from(0.1)'
         #[structopt(long)]
         fun: FunctionChooser,
 
@@ -268,9 +486,318 @@ pub enum Opt
         /// use every nth step
         every: usize,
 
+        /// Percentiles to calculate, e.g. '0.9' or '0.05,0.5,0.95'.
+        /// Percentiles are linearly interpolated between the closest values
+        #[structopt(long, short, required = true, use_delimiter = true)]
+        percent: Vec<f64>,
+
+        /// Calculate the feature as float, e.g. interpolated crossing times or expressions
+        #[structopt(long)]
+        float: bool,
+
+        #[structopt(long)]
+        /// norm curves before calculation, implies float
+        normed: bool,
+
+        /// number of bootstrap resamples for the confidence intervals of the percentiles.
+        /// Default: 0, i.e., no confidence intervals
+        #[structopt(long, default_value = "0")]
+        bootstrap_samples: usize,
+
+        /// seed for the bootstrap, bin i uses seed + i
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+
+        /// confidence level of the interval, 
+        /// e.g. 0.95 for the 2.5 and 97.5 percentiles
+        #[structopt(long, default_value = "0.95")]
+        confidence: f64,
+
+        /// flag bins where the confidence interval of a percentile is wider than this.
+        /// Requires bootstrap_samples
+        #[structopt(long)]
+        max_width: Option<f64>,
+
+        /// Estimate the percentiles with a t-digest of this compression per energy bin
+        /// instead of storing all values, e.g. 100. 
+        /// Higher compression is more accurate and needs more memory.
+        /// Not compatible with bootstrap_samples
+        #[structopt(long)]
+        sketch: Option<f64>,
+
+        #[structopt(flatten)]
+        energy: EnergyArgs
+    },
+    /// Print a summary of the data files, e.g., energy range, curve lengths and header
+    Inspect {
+        /// filenames (globbing pattern)
+        #[structopt(long, short)]
+        files: String,
+
+        /// number of bins used for the printed distributions
+        #[structopt(long, short, default_value = "20")]
+        bins: usize,
+
+        /// name of the json file to create.
+        /// Default: inspect.SUFFIX.json
+        #[structopt(long)]
+        json: Option<String>,
+    },
+    /// Calculate several features in one pass over the data files.
+    /// Every task creates its own output file
+    Multi {
+        /// number of nodes.
+        /// Default: N from the file headers
+        #[structopt(long,short)]
+        n: Option<usize>,
+
+        /// number of bins for energy
         #[structopt(long, short)]
-        /// Percent val
-        percent: f64,
+        bins: usize,
+
+        /// filenames
+        #[structopt(long, short)]
+        files: String,
+
+        /// save file to create
+        #[structopt(long, default_value= "")]
+        save: String,
+
+        #[structopt(long, short)]
+        /// use every nth step
+        every: usize,
+
+        /// Task in the form 'feature:kind[:args]', can be used multiple times.
+        /// feature: same as 'fun' of Heatmap2 for heatmap and percent,
+        /// same as 'hist_reduce' of Histogram for histogram,
+        /// kind: 'heatmap' (args e.g. 'u 100 0 100'), 'percent' (args e.g. '0.9') or 'histogram'.
+        /// Example: -t 'index_max:heatmap:u 100 0 1000' -t 'val_max:percent:0.9' -t auc:histogram
+        #[structopt(long = "task", short = "t", required = true, number_of_values = 1)]
+        tasks: Vec<MultiTask>,
+
+        /// norm the curves by their maximum before calculating the features of heatmap and percent tasks.
+        /// Percent features are floats in this case
+        #[structopt(long)]
+        normed: bool,
+
+        /// calculate the features of percent tasks as floats, e.g. interpolated crossing times
+        #[structopt(long)]
+        float: bool,
+
+        #[structopt(flatten)]
+        error: ErrorArgs,
+
+        #[structopt(flatten)]
+        plot: PlotArgs,
+
+        /// automatically call gnuplot to plot the resulting heatmaps
+        #[structopt(long, short)]
+        gnuplot: bool,
+
+        #[structopt(short, default_value = "1")]
+        /// number of threads to use for bootstrapping the histograms
+        j: usize,
+
+        #[structopt(long)]
+        /// hide progress bar
+        no_p_bar: bool,
+
+        /// Additionally export the matrices and axes, e.g. 'npz' or 'npy,csv'.
+        /// valid: npy, npz, csv. A JSON file with the metadata is written as well
+        #[structopt(long, use_delimiter = true)]
+        export: Vec<ExportFormat>,
+
+        #[structopt(flatten)]
+        energy: EnergyArgs
+    },
+    /// Run all analyses described in a TOML job file
+    Job {
+        /// the job file
+        file: String,
+
+        /// only print the resulting command lines
+        #[structopt(long)]
+        dry_run: bool,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MultiOpts{
+    pub n: usize,
+    pub bins: usize,
+    pub files: String,
+    pub save: String,
+    pub every: usize,
+    pub tasks: Vec<MultiTask>,
+    pub normed: bool,
+    pub float: bool,
+    pub error: ErrorConfig,
+    pub plot: PlotArgs,
+    pub gnuplot_exec: bool,
+    pub j: usize,
+    pub no_p_bar: bool,
+    pub suffix: String,
+    pub export: Vec<ExportFormat>,
+    pub energy: EnergyAxis,
+    pub params: HeaderParams
+}
+
+impl MultiOpts{
+    pub fn generate_filename<D: std::fmt::Display>(&self, task: &MultiTask, extension: D) -> String
+    {
+        let mode = match task {
+            MultiTask::Histogram(_) => "",
+            _ if self.normed => "_normed",
+            MultiTask::Percent(..) if self.float => "_float",
+            _ => ""
+        };
+        format!(
+            "v{}_{}{}_{}_b{}_e{}_{}.{}.{}", 
+            env!("CARGO_PKG_VERSION"),
+            task,
+            mode,
+            self.energy.name(),
+            self.bins,
+            self.every,
+            self.save,
+            &self.suffix,
+            extension
+        )
+    }
+}
+
+impl TryFrom<Opt> for MultiOpts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
+        match opt {
+            Opt::Multi {
+                n,
+                bins,
+                files,
+                save,
+                every,
+                mut tasks,
+                normed,
+                float,
+                error,
+                plot,
+                gnuplot,
+                j,
+                no_p_bar,
+                export,
+                energy
+            } => {
+                let params = resolve_params(&files)?;
+                let n = choose_n(n, &params)?;
+                let energy = energy.axis(n, bins, params.n())?;
+                tasks.iter_mut()
+                    .for_each(|task| task.bind_n(n));
+                let error = error.config()?;
+                if j == 0 {
+                    return Err("0 threds not allowed, use at least 1: INVALID j".to_owned());
+                }
+                let suffix = match get_suffix(&files){
+                    Ok(suf) => suf,
+                    Err(set) => {
+                        eprintln!("WARNING: Sufix do not match! Found {:?}", set);
+                        set.into_iter()
+                            .collect::<Vec<String>>()
+                            .join("_")
+                    }
+                };
+                Ok(MultiOpts{
+                    n,
+                    bins,
+                    files,
+                    save,
+                    every,
+                    tasks,
+                    normed,
+                    float,
+                    error,
+                    plot,
+                    gnuplot_exec: gnuplot,
+                    j,
+                    no_p_bar,
+                    suffix,
+                    export,
+                    energy,
+                    params
+                })
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobOpts{
+    pub file: String,
+    pub dry_run: bool
+}
+
+impl TryFrom<Opt> for JobOpts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
+        match opt {
+            Opt::Job {
+                file,
+                dry_run
+            } => {
+                Ok(JobOpts{
+                    file,
+                    dry_run
+                })
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InspectOpts{
+    pub files: String,
+    pub bins: usize,
+    pub json: String,
+}
+
+impl TryFrom<Opt> for InspectOpts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
+        match opt {
+            Opt::Inspect {
+                files,
+                bins,
+                json
+            } => {
+                if bins == 0 {
+                    return Err("bins has to be at least 1".to_owned());
+                }
+                let json = json.unwrap_or_else(
+                    || {
+                        let suffix = match get_suffix(&files){
+                            Ok(suf) => suf,
+                            Err(set) => {
+                                eprintln!("WARNING: Sufix do not match! Found {:?}", set);
+                                set.into_iter()
+                                    .collect::<Vec<String>>()
+                                    .join("_")
+                            }
+                        };
+                        format!("inspect.{}.json", suffix)
+                    }
+                );
+                Ok(InspectOpts{
+                    files,
+                    bins,
+                    json
+                })
+            },
+            _ => unreachable!()
+        }
     }
 }
 
@@ -281,38 +808,115 @@ pub struct PercentOpts{
     pub files: String,
     pub fun: FunctionChooser,
     pub every: usize,
-    pub percent: f64,
-    pub suffix: String
+    pub percentiles: Vec<f64>,
+    pub float: bool,
+    pub normed: bool,
+    /// None if no confidence intervals are requested
+    pub bootstrap: Option<PercentBootstrap>,
+    /// compression of the t-digests, None to store all values
+    pub sketch: Option<f64>,
+    pub suffix: String,
+    pub energy: EnergyAxis,
+    pub params: HeaderParams
 }
 
 impl PercentOpts{
+    /// The feature is a float for `--float`, `--normed` and expressions, an integer otherwise
+    pub fn feature(&self) -> FeatureSpec
+    {
+        FeatureSpec{
+            fun: self.fun.clone(),
+            float: self.float || self.normed || self.fun.is_float(),
+            normed: self.normed,
+            filter: None,
+            every: self.every
+        }
+    }
+
     pub fn generate_filename<D: std::fmt::Display>(&self, extension: D) -> String
     {
         format!(
-            "v{}_{}_N{}_b{}_e{}_p{}.{}.{}", 
+            "v{}_{}_{}_b{}_e{}_p{}{}{}.{}.{}", 
             env!("CARGO_PKG_VERSION"),
             self.fun,
-            self.n,
+            self.energy.name(),
             self.bins,
             self.every,
-            self.percent,
+            self.percentiles
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join("_"),
+            if self.normed {
+                "_normed"
+            } else if self.float {
+                "_float"
+            } else {
+                ""
+            },
+            self.sketch
+                .map(|compression| format!("_td{}", compression))
+                .unwrap_or_default(),
             &self.suffix,
             extension
         )
     }
 }
 
-impl From<Opt> for PercentOpts{
-    fn from(opt: Opt) -> Self {
+impl TryFrom<Opt> for PercentOpts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
         match opt {
             Opt::Percent {
                 n,
                 files,
                 every,
-                fun,
+                mut fun,
                 bins,
                 percent,
+                float,
+                normed,
+                bootstrap_samples,
+                seed,
+                confidence,
+                max_width,
+                sketch,
+                energy
             } => {
+                if let Some(p) = percent.iter().find(|p| !(0.0..=1.0).contains(*p)) {
+                    return Err(format!("percentile {} has to be in [0, 1]", p));
+                }
+                if !(confidence > 0.0 && confidence < 1.0) {
+                    return Err(format!("confidence has to be in (0, 1), found {}", confidence));
+                }
+                if max_width.is_some() && bootstrap_samples == 0 {
+                    return Err("max_width requires bootstrap_samples".to_owned());
+                }
+                if let Some(compression) = sketch {
+                    if compression.is_nan() || compression < 1.0 {
+                        return Err(format!("sketch compression has to be at least 1, found {}", compression));
+                    }
+                    if bootstrap_samples > 0 {
+                        return Err("the bootstrap needs all values, it can not be combined with sketch".to_owned());
+                    }
+                }
+                let bootstrap = if bootstrap_samples > 0 {
+                    Some(
+                        PercentBootstrap{
+                            samples: bootstrap_samples,
+                            seed,
+                            confidence,
+                            max_width
+                        }
+                    )
+                } else {
+                    None
+                };
+                let params = resolve_params(&files)?;
+                let n = choose_n(n, &params)?;
+                let energy = energy.axis(n, bins, params.n())?;
+                fun.bind_n(n);
                 let suffix = match get_suffix(&files){
                     Ok(suf) => suf,
                     Err(set) => {
@@ -322,21 +926,28 @@ impl From<Opt> for PercentOpts{
                             .join("_")
                     }
                 };
-                PercentOpts{
+                Ok(PercentOpts{
                     n,
                     files,
                     fun,
                     every,
                     bins,
-                    percent,
-                    suffix
-                }
+                    percentiles: percent,
+                    float,
+                    normed,
+                    bootstrap,
+                    sketch,
+                    suffix,
+                    energy,
+                    params
+                })
             },
             _ => unreachable!()
         }
     }
 }
 
+/// How two curves are compared in the Heatmap subcommand
 #[derive(Copy, Clone, Debug)]
 pub enum Mode
 {
@@ -376,19 +987,34 @@ pub struct Heatmap2Opts
     pub fun: FunctionChooser,
     pub normed: bool,
     pub heatmap_builder: HeatmapBuilder,
+    pub filter: Option<Expression>,
     pub gnuplot_exec: bool,
-    pub rgb: bool,
-    pub c0: bool
+    pub plot: PlotArgs,
+    pub export: Vec<ExportFormat>,
+    pub energy: EnergyAxis,
+    pub params: HeaderParams
 }
 
 impl Heatmap2Opts{
+    /// The feature is a float, if the heatmap has a float axis
+    pub fn feature(&self) -> FeatureSpec
+    {
+        FeatureSpec{
+            fun: self.fun.clone(),
+            float: matches!(self.heatmap_builder, HeatmapBuilder::F64Auto(_) | HeatmapBuilder::F64Heatmap{..}),
+            normed: self.normed,
+            filter: self.filter.clone(),
+            every: self.every
+        }
+    }
+
     pub fn generate_filename<D: std::fmt::Display>(&self, extension: D) -> String
     {
         format!(
-            "v{}_{}_N{}_b{}_{}_e{}_{}.{}.{}", 
+            "v{}_{}_{}_b{}_{}_e{}_{}.{}.{}", 
             env!("CARGO_PKG_VERSION"),
             self.fun,
-            self.n,
+            self.energy.name(),
             self.bins,
             self.heatmap_builder,
             self.every,
@@ -400,8 +1026,10 @@ impl Heatmap2Opts{
 }
 
 
-impl From<Opt> for Heatmap2Opts{
-    fn from(opt: Opt) -> Self {
+impl TryFrom<Opt> for Heatmap2Opts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
         match opt {
             Opt::Heatmap2 {
                 n,
@@ -409,22 +1037,22 @@ impl From<Opt> for Heatmap2Opts{
                 save,
                 no_p_bar,
                 every,
-                fun,
+                mut fun,
                 normed,
                 heatmap,
                 bins,
                 gnuplot,
-                rgb,
-                c0
+                mut filter,
+                plot,
+                export,
+                energy
             } => {
-                let len = if c0 {
-                    n + 1
-                } else {
-                    n
-                };
-                if len % bins != 0 {
-                    eprintln!("ERROR: {} does nt divide by {} - rest is {}", len, bins, len % bins);
-                    exit(-1);
+                let params = resolve_params(&files)?;
+                let n = choose_n(n, &params)?;
+                let energy = energy.axis(n, bins, params.n())?;
+                fun.bind_n(n);
+                if let Some(filter) = filter.as_mut() {
+                    filter.bind_n(n);
                 }
                 let suffix = match get_suffix(&files){
                     Ok(suf) => suf,
@@ -436,10 +1064,11 @@ impl From<Opt> for Heatmap2Opts{
                     }
                 };
                 
-                Heatmap2Opts{
+                Ok(Heatmap2Opts{
                     n,
                     bins,
                     heatmap_builder: heatmap,
+                    filter,
                     files,
                     save,
                     no_p_bar,
@@ -448,9 +1077,11 @@ impl From<Opt> for Heatmap2Opts{
                     suffix,
                     normed,
                     gnuplot_exec: gnuplot,
-                    rgb,
-                    c0
-                }
+                    plot,
+                    export,
+                    energy,
+                    params
+                })
             },
             _ => unreachable!()
         }
@@ -462,23 +1093,28 @@ pub struct HistogramOpts{
     pub n: usize,
     pub bins: usize,
     pub files: String,
-    pub bin_size: usize,
     pub save: String,
     pub j: usize,
     pub no_p_bar: bool,
     pub every: usize,
     pub suffix: String,
     pub hist_reduce: HistReduce,
+    pub error: ErrorConfig,
+    pub bin_stats: bool,
+    pub quantiles: Vec<f64>,
+    pub dump_samples: bool,
+    pub energy: EnergyAxis,
+    pub params: HeaderParams
 }
 
 impl HistogramOpts{
     pub fn generate_filename<D: std::fmt::Display>(&self, extension: D) -> String
     {
         format!(
-            "v{}_{:?}_N{}_b{}_e{}_{}.{}.{}", 
+            "v{}_{}_{}_b{}_e{}_{}.{}.{}", 
             env!("CARGO_PKG_VERSION"),
             self.hist_reduce,
-            self.n,
+            self.energy.name(),
             self.bins,
             self.every,
             self.save,
@@ -488,8 +1124,10 @@ impl HistogramOpts{
     }
 }
 
-impl From<Opt> for HistogramOpts{
-    fn from(opt: Opt) -> Self {
+impl TryFrom<Opt> for HistogramOpts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
         match opt {
             Opt::Histogram {
                 n, 
@@ -499,12 +1137,21 @@ impl From<Opt> for HistogramOpts{
                 j,
                 no_p_bar,
                 every,
-                hist_reduce
+                mut hist_reduce,
+                error,
+                bin_stats,
+                quantiles,
+                dump_samples,
+                energy
             } => {
-                if n % bins != 0 {
-                    eprintln!("ERROR: {} does nt divide by {} - rest is {}", n, bins, n % bins);
-                    exit(-1);
+                if let Some(q) = quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+                    return Err(format!("quantiles have to be in [0, 1], found {}", q));
                 }
+                let error = error.config()?;
+                let params = resolve_params(&files)?;
+                let n = choose_n(n, &params)?;
+                let energy = energy.axis(n, bins, params.n())?;
+                hist_reduce.bind_n(n);
                 let suffix = match get_suffix(&files){
                     Ok(suf) => suf,
                     Err(set) => {
@@ -515,18 +1162,23 @@ impl From<Opt> for HistogramOpts{
                     }
                 };
                 
-                HistogramOpts{
+                Ok(HistogramOpts{
                     n,
                     bins,
                     files,
-                    bin_size: n / bins,
                     save,
                     j,
                     no_p_bar,
                     every,
                     hist_reduce,
-                    suffix
-                }
+                    suffix,
+                    error,
+                    bin_stats,
+                    quantiles,
+                    dump_samples,
+                    energy,
+                    params
+                })
             },
             _ => unreachable!()
         }
@@ -536,10 +1188,8 @@ impl From<Opt> for HistogramOpts{
 #[derive(Clone)]
 pub struct HeatmapOpts{
     pub n: usize,
-    pub n_real: Option<usize>,
     pub bin_count: usize,
     pub files: String,
-    pub bin_size: usize,
     pub save: String,
     pub j: usize,
     pub no_p_bar: bool,
@@ -549,13 +1199,24 @@ pub struct HeatmapOpts{
     pub suffix: String,
     pub data_mode: DataMode,
     pub norm: bool,
-    pub no_subtract: bool,
     pub max_entries: Option<NonZeroUsize>,
     pub print_bin_lens: bool,
-    pub c0: bool
+    pub export: Vec<ExportFormat>,
+    pub energy: EnergyAxis,
+    pub params: HeaderParams
 }
 
 impl HeatmapOpts{
+    /// names of the mean, iteration and curve count files
+    pub fn stats_filenames(&self) -> [String; 3]
+    {
+        [
+            self.generate_filename("stats.mean.xz"),
+            self.generate_filename("stats.iterations"),
+            self.generate_filename("stats.curve_count")
+        ]
+    }
+
     pub fn generate_filename<D: std::fmt::Display>(&self, extension: D) -> String
     {
         let norm = if self.norm {
@@ -563,17 +1224,12 @@ impl HeatmapOpts{
         } else {
             "NoNorm"
         };
-        let n_actual = if let Some(val) = self.n_real {
-            val
-        } else {
-            self.n
-        };
         format!(
             "v{}_{:?}_{}_N{}_Reach{}_b{}_e{}_{}.{}.{}", 
             env!("CARGO_PKG_VERSION"),
             self.mode,
             norm,
-            n_actual,
+            self.energy.n_actual(),
             self.n,
             self.bin_count,
             self.every,
@@ -614,11 +1270,14 @@ pub fn get_suffix<S: AsRef<str>>(pattern: S) -> Result<String, HashSet<String>>
     }
 }
 
-impl From<Opt> for HeatmapOpts{
-    fn from(opt: Opt) -> Self {
+impl TryFrom<Opt> for HeatmapOpts{
+    type Error = String;
+
+    fn try_from(opt: Opt) -> Result<Self, Self::Error> {
         match opt {
             Opt::Heatmap{
                 n, 
+                n_real,
                 bins,
                 files,
                 save,
@@ -628,21 +1287,17 @@ impl From<Opt> for HeatmapOpts{
                 cutoff,
                 mode,
                 no_norm,
-                n_real,
-                no_subtract,
                 max_entries,
                 print_bin_lens,
-                c0
+                export,
+                energy
             } => {
-                let len = if c0 {
-                    n + 1
-                } else {
-                    n
-                };
-                if len % bins != 0 {
-                    eprintln!("ERROR: {} does nt divide by {} - rest is {}", len, bins, len % bins);
-                    exit(-1);
+                if j == 0 {
+                    return Err("0 threds not allowed, use at least 1: INVALID j".to_owned());
                 }
+                // n is the number of reachable nodes, only n_real can be taken from the header
+                let params = resolve_params(&files)?;
+                let energy = energy.axis(n, bins, n_real.or_else(|| params.n()))?;
                 let suffix = match get_suffix(&files){
                     Ok(suf) => suf,
                     Err(set) => {
@@ -656,11 +1311,10 @@ impl From<Opt> for HeatmapOpts{
                     0..=2 => DataMode::Sparse,
                     _ => DataMode::Naive,
                 };
-                Self{
+                Ok(Self{
                     n,
                     bin_count: bins,
                     files,
-                    bin_size: n / bins,
                     save,
                     j,
                     no_p_bar,
@@ -670,12 +1324,12 @@ impl From<Opt> for HeatmapOpts{
                     suffix,
                     data_mode,
                     norm: !no_norm,
-                    n_real,
-                    no_subtract,
                     max_entries,
                     print_bin_lens,
-                    c0
-                }
+                    export,
+                    energy,
+                    params
+                })
             },
             _ => unreachable!()
         }
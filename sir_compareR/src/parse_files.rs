@@ -1,20 +1,22 @@
 use std::fmt::Debug;
-use std::{io::*, ops::Div};
-use std::fs::*;
-use flate2::read::*;
+use std::io::*;
 use std::path::Path;
 use crate::parse_cmd::*;
 use crate::stats::Data;
+use crate::energy::EnergyAxis;
 use std::result::Result;
-use lzma::LzmaReader;
+use crate::input::open_input;
 use std::sync::atomic::*;
 
 pub static UNFINISHED_ENCOUNTERED: AtomicBool = AtomicBool::new(false);
 pub static UNFINISHED_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+/// How the curves are stored
 #[derive(Debug, Copy, Clone)]
 pub enum DataMode{
+    /// store the whole curve
     Naive,
+    /// store the curve only up to the extinction index
     Sparse,
 }
 
@@ -54,7 +56,7 @@ fn parse_and_group<R, F>
     norm: bool,
 )
 where
-        F: Fn(usize) -> usize,
+        F: Fn(usize) -> Option<usize>,
         R: Read,
 {
     dbg!("HERE");
@@ -112,7 +114,9 @@ where
                 }
                 
                 // append to correct bin
-                data.push(index_func(energy), vec);
+                if let Some(index) = index_func(energy) {
+                    data.push(index, vec);
+                }
             }
         );
         dbg!("DONE");
@@ -127,7 +131,7 @@ fn parse_and_group_naive<R, F>
     norm: bool,
 )
 where
-    F: Fn(usize) -> usize,
+    F: Fn(usize) -> Option<usize>,
     R: Read,
 {
     let buf_reader = BufReader::new(reader);
@@ -155,26 +159,24 @@ where
                 }
 
                 // append to correct bin
-                data.push(index_func(energy), vec);
+                if let Some(index) = index_func(energy) {
+                    data.push(index, vec);
+                }
             }
         );
     dbg!("read");
 }
 
+/// Parses all files matching the glob and groups the curves by energy
 pub fn parse_and_group_all_files(opts: HeatmapOpts) -> Data
 {
     let mut data = Data::new_from_heatmap_options(&opts);
-    let index = |energy: usize| {
-        if opts.no_subtract {
-            energy 
-        }else {
-            energy - 1
-        }.div(opts.bin_size)
-    };
+    let index = |energy: usize| opts.energy.index(energy);
     for entry in glob::glob(&opts.files).unwrap().filter_map(Result::ok) {
         dbg!(&entry);
         parse_and_group_file(entry, opts.every, &mut data, index, opts.data_mode, opts.norm);
     }
+    EnergyAxis::report_out_of_range();
     data
 }
 
@@ -190,37 +192,14 @@ pub fn parse_and_group_file<P, F>
     norm: bool
 )
 where P: AsRef<Path> + Debug,
-    F: Fn(usize) -> usize,
+    F: Fn(usize) -> Option<usize>,
 {
     dbg!(format!("parsing {filename:?}"));
-    let ending = filename.as_ref()
-        .extension()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let file = File::open(&filename).unwrap();
+    let reader = open_input(&filename);
 
-    match ending {
-        "gz" => {
-            let decoder = GzDecoder::new(file);
-            match data_mode {
-               DataMode::Sparse => parse_and_group(decoder, every, data, index_func, norm),
-               DataMode::Naive => parse_and_group_naive(decoder, every, data, index_func, norm),
-            };
-        },
-        "xz" => {
-            let decoder = LzmaReader::new_decompressor(file).unwrap();
-            match data_mode {
-                DataMode::Sparse => parse_and_group(decoder, every, data, index_func, norm),
-                DataMode::Naive => parse_and_group_naive(decoder, every, data, index_func, norm),
-            };
-        },
-        _ => {
-            match data_mode {
-                DataMode::Sparse => parse_and_group(file, every, data, index_func, norm),
-                DataMode::Naive => parse_and_group_naive(file, every, data, index_func, norm),
-            };
-        }
+    match data_mode {
+        DataMode::Sparse => parse_and_group(reader, every, data, index_func, norm),
+        DataMode::Naive => parse_and_group_naive(reader, every, data, index_func, norm),
     };
     dbg!("finished parsing file");
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use crate::analyse::*;
-use crate::*;
-use std::{io::*, num::NonZeroUsize, sync::Mutex};
+use crate::parse_cmd::HeatmapOpts;
+use std::{io::*, num::NonZeroUsize, sync::{Mutex, RwLock}};
 use std::fs::*;
 use std::{env, cmp::Reverse};
 use rand::prelude::SliceRandom;
@@ -16,9 +16,13 @@ lazy_static! {
     {
         Mutex::new(Pcg64::new(0xcafef00dd15ea5e5, 0xa02bdbf7bb3c0a7ac28fa16a64abf96))
     };
+
+    /// used instead of the actual cmd arguments, e.g., for analyses of job files
+    static ref CMD_ARGS: RwLock<Option<String>> = RwLock::new(None);
 }
 
 
+/// Result of comparing the curves of each pair of energy bins
 #[derive(Clone)]
 pub struct Stats{
     mean: Vec<Vec<f64>>,
@@ -72,11 +76,21 @@ impl Stats {
 
 pub fn get_cmd_args() -> String 
 {
+    if let Some(args) = CMD_ARGS.read().unwrap().as_ref() {
+        return args.clone();
+    }
     // get cmd arguments
     let args: Vec<String> = env::args().collect();
     args.join(" ")
 }
 
+/// Overwrite the cmd arguments that are written into the output files
+pub fn set_cmd_args(args: Option<String>)
+{
+    *CMD_ARGS.write().unwrap() = args;
+}
+
+/// Writes [Stats] into the mean, iteration count and curve count files
 pub struct StatsWriter<W, W2>
 {
     pub(crate) mean_writer: W,
@@ -127,9 +141,7 @@ impl<W: Write, W2: Write> StatsWriter<W, W2>{
 impl StatsWriter<File, File>{
     pub fn new_from_heatmap_opts(opts: HeatmapOpts) -> StatsWriter<LzmaWriter<BufWriter<File>>, BufWriter<File>>
     {
-        let mean_name = opts.generate_filename("stats.mean.xz");
-        let iteration_name = opts.generate_filename("stats.iterations");
-        let curve_count_name = opts.generate_filename("stats.curve_count");
+        let [mean_name, iteration_name, curve_count_name] = opts.stats_filenames();
         println!("Generated:\n{}\n{}\n{}", &mean_name, &iteration_name, &curve_count_name);
 
         let mean_writer = File::create(mean_name).unwrap();
@@ -151,6 +163,7 @@ impl StatsWriter<File, File>{
         };
         writeln!(stats, "#{}", get_cmd_args()).unwrap();
         writeln!(stats, "#{}", env::current_dir().unwrap().to_str().unwrap()).unwrap();
+        opts.params.write_header(&mut stats).unwrap();
         stats
     }
 }
@@ -181,6 +194,7 @@ impl<W: Write> From<StatsWriter<W, W>> for StatsWriter<BufWriter<W>, BufWriter<W
 }
 
 
+/// Curves grouped by energy bin, i.e., `data[bin][curve][time]`
 pub struct Data
 {
     pub data: Vec<Vec<Vec<f64>>>,
@@ -350,6 +364,7 @@ impl Data{
 
 }
 
+/// Position and value of the maximum of each curve, grouped by energy bin
 pub struct IndexData{
     pub index_data: Vec<Vec<isize>>,
     pub val_max_data: Vec<Vec<f64>>
@@ -4,12 +4,14 @@ use parse_file::*;
 mod integral;
 use integral::*;
 use std::path::PathBuf;
-use std::fs::*;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, Write};
 use std::env;
 mod rate_function;
 use rate_function::*;
-use std::fmt::*;
+use sir_compare_r::input::*;
+mod output;
+use output::*;
+use std::path::Path;
 
 fn main() {
     let opts = NormOpts::from_args();
@@ -21,28 +23,28 @@ fn main() {
             let mut curve = curves.pop().unwrap();
             let path = files.pop().unwrap();
             norm(&mut curve, opts.n);
-            write_curve(path, curve, n);
+            write_curve(path, curve, n, opts.compress);
         } else {
             let mut curve = merge(&curves);
             norm(&mut curve, opts.n);
             let path = format!("N{}merged{}.merg", opts.n, curves.len());
-            let file = File::create(path).unwrap();
-            let buf = BufWriter::new(file);
-            write_curve_merged(buf, curve, n, files);
+            let mut out = opts.compress.create(&path);
+            write_curve_merged(&mut out, curve, n, files);
+            out.finish();
         }
     }else {
         if curves.len() == 1 {
             let mut curve = curves.pop().unwrap();
             let path = files.pop().unwrap();
             to_rate_fun(&mut curve, n);
-            write_rate_fun(path, curve, n);
+            write_rate_fun(path, curve, n, opts.compress);
         } else {
             let mut curve = merge(&curves);
             to_rate_fun(&mut curve, n);
             let path = format!("N{}merged{}.rate", opts.n, curves.len());
-            let file = File::create(path).unwrap();
-            let buf = BufWriter::new(file);
-            write_rate_fun_merged(curve, n, buf, files);
+            let mut out = opts.compress.create(&path);
+            write_rate_fun_merged(curve, n, &mut out, files);
+            out.finish();
         }
     }
 
@@ -51,7 +53,7 @@ fn main() {
 #[derive(Debug, StructOpt)]
 #[structopt(name = "norm", about = "Norm probability density")]
 pub struct NormOpts{
-    /// which file(s) to load
+    /// which file(s) to load, files ending with gz or xz are decompressed
     #[structopt(short, long)]
     files: String,
 
@@ -63,21 +65,16 @@ pub struct NormOpts{
     #[structopt(short, long)]
     rate_function: bool,
 
-}
+    /// compression of the output files, valid: none, gz, xz
+    #[structopt(long, default_value = "none")]
+    compress: Compression,
 
+}
 
-pub fn write_curve(path: PathBuf, curve: Vec<CurveEntry>, n: f64)
+/// Copies the leading comment lines of the (possibly compressed) input file
+fn copy_header<W: Write>(path: &Path, out: &mut W)
 {
-    let file = File::open(&path).unwrap();
-    
-    let str = path.to_str().unwrap();
-    let mut s = str.to_owned();
-    s.push_str(".normed");
-    println!("new_file: {:?}", &s);
-    let out = File::create(s).unwrap();
-    let mut out = BufWriter::new(out);
-
-    let buf = BufReader::new(file);
+    let buf = BufReader::new(open_input(path));
     
     for line in buf.lines(){
         let line = line.unwrap();
@@ -88,6 +85,22 @@ pub fn write_curve(path: PathBuf, curve: Vec<CurveEntry>, n: f64)
             break;
         }
     }
+}
+
+/// name of the output file for the input `path`, e.g. "a.dat.xz" -> "a.dat.normed"
+fn output_name(path: &Path, ending: &str) -> String
+{
+    let path = strip_compression(path);
+    let mut s = path.to_str().unwrap().to_owned();
+    s.push_str(ending);
+    s
+}
+
+
+pub fn write_curve(path: PathBuf, curve: Vec<CurveEntry>, n: f64, compression: Compression)
+{
+    let mut out = compression.create(&output_name(&path, ".normed"));
+    copy_header(&path, &mut out);
     write!(out, "#").unwrap();
     for arg in env::args()
     {
@@ -108,9 +121,10 @@ pub fn write_curve(path: PathBuf, curve: Vec<CurveEntry>, n: f64)
             entry.right
         ).unwrap();
     }
+    out.finish();
 }
 
-pub fn write_curve_merged<W: Write>(mut out: W, curve: Vec<CurveEntry>, n: f64, files: Vec<PathBuf>)
+pub fn write_curve_merged<W: Write>(out: &mut W, curve: Vec<CurveEntry>, n: f64, files: Vec<PathBuf>)
 {
 
     write!(out, "#").unwrap();
@@ -138,7 +152,7 @@ pub fn write_curve_merged<W: Write>(mut out: W, curve: Vec<CurveEntry>, n: f64,
     }
 }
 
-pub fn write_rate_fun_merged<W: Write>(curve: Vec<CurveEntry>, n: f64, mut out: W, files: Vec<PathBuf>)
+pub fn write_rate_fun_merged<W: Write>(curve: Vec<CurveEntry>, n: f64, out: &mut W, files: Vec<PathBuf>)
 {
     write!(out, "#").unwrap();
     for arg in env::args()
@@ -165,28 +179,10 @@ pub fn write_rate_fun_merged<W: Write>(curve: Vec<CurveEntry>, n: f64, mut out:
     }
 }
 
-pub fn write_rate_fun(path: PathBuf, curve: Vec<CurveEntry>, n: f64)
+pub fn write_rate_fun(path: PathBuf, curve: Vec<CurveEntry>, n: f64, compression: Compression)
 {
-    let file = File::open(&path).unwrap();
-    
-    let str = path.to_str().unwrap();
-    let mut s = str.to_owned();
-    s.push_str(".rate");
-    println!("new_file: {:?}", &s);
-    let out = File::create(s).unwrap();
-    let mut out = BufWriter::new(out);
-
-    let buf = BufReader::new(file);
-    
-    for line in buf.lines(){
-        let line = line.unwrap();
-        let trim = line.trim_start();
-        if trim.starts_with("#") || trim.is_empty() {
-            writeln!(out, "{}", line).unwrap();
-        } else {
-            break;
-        }
-    }
+    let mut out = compression.create(&output_name(&path, ".rate"));
+    copy_header(&path, &mut out);
     write!(out, "#").unwrap();
     for arg in env::args()
     {
@@ -207,5 +203,6 @@ pub fn write_rate_fun(path: PathBuf, curve: Vec<CurveEntry>, n: f64)
             entry.right
         ).unwrap();
     }
+    out.finish();
 }
 
@@ -0,0 +1,99 @@
+use lzma::LzmaWriter;
+use flate2::{write::GzEncoder, Compression as GzCompression};
+use std::io::*;
+use std::fs::*;
+use std::str::FromStr;
+use std::fmt;
+
+/// Compression of the output files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression{
+    None,
+    Gz,
+    Xz
+}
+
+impl FromStr for Compression {
+    type Err = &'static str;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" | "no" => Ok(Compression::None),
+            "gz" | "gzip" => Ok(Compression::Gz),
+            "xz" | "lzma" => Ok(Compression::Xz),
+            _ => Err("Invalid compression requested, valid: none, gz, xz")
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gz => write!(f, "gz"),
+            Compression::Xz => write!(f, "xz")
+        }
+    }
+}
+
+impl Compression{
+    /// file name with the matching extension appended, e.g. "a.rate.xz"
+    pub fn file_name(&self, name: &str) -> String
+    {
+        match self {
+            Compression::None => name.to_owned(),
+            _ => format!("{}.{}", name, self)
+        }
+    }
+
+    /// Creates the file, see [file_name](Self::file_name), and prints its name
+    pub fn create(&self, name: &str) -> OutputWriter
+    {
+        let name = self.file_name(name);
+        println!("new_file: {:?}", &name);
+        let buf = BufWriter::new(File::create(name).unwrap());
+        match self {
+            Compression::None => OutputWriter::Plain(buf),
+            Compression::Gz => OutputWriter::Gz(GzEncoder::new(buf, GzCompression::default())),
+            Compression::Xz => OutputWriter::Xz(LzmaWriter::new_compressor(buf, 6).unwrap())
+        }
+    }
+}
+
+/// Writer for plain, gz and xz compressed files.
+/// Call [finish](OutputWriter::finish) when done, otherwise compressed files are truncated
+pub enum OutputWriter{
+    Plain(BufWriter<File>),
+    Gz(GzEncoder<BufWriter<File>>),
+    Xz(LzmaWriter<BufWriter<File>>)
+}
+
+impl Write for OutputWriter{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Self::Plain(w) => w.write(buf),
+            Self::Gz(w) => w.write(buf),
+            Self::Xz(w) => w.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Self::Plain(w) => w.flush(),
+            Self::Gz(w) => w.flush(),
+            Self::Xz(w) => w.flush()
+        }
+    }
+}
+
+impl OutputWriter{
+    /// finishes the compression and flushes the file
+    pub fn finish(self)
+    {
+        let mut buf = match self {
+            Self::Plain(w) => w,
+            Self::Gz(w) => w.finish().unwrap(),
+            Self::Xz(w) => w.finish().unwrap()
+        };
+        buf.flush().unwrap();
+    }
+}
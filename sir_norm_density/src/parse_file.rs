@@ -1,6 +1,6 @@
 use std::io::{Read, BufReader, BufRead};
-use std::fs::*;
 use std::path::PathBuf;
+use sir_compare_r::input::open_input;
 
 pub fn parse_files(files: &str) -> (Vec<PathBuf>, Vec<Vec<CurveEntry>>)
 {
@@ -10,10 +10,8 @@ pub fn parse_files(files: &str) -> (Vec<PathBuf>, Vec<Vec<CurveEntry>>)
         .collect();
     
     let curve_vec: Vec<_> = files.iter()
-        .map(|path| {
-            let file = File::open(path).unwrap();
-            parse_file(file)
-        }).collect();
+        .map(|path| parse_file(open_input(path)))
+        .collect();
     (files, curve_vec)
 }
 
@@ -1,9 +1,7 @@
 use structopt::StructOpt;
 use std::{io::{BufRead, BufReader, Read, Write}, num::NonZeroUsize};
-use std::fs::File;
 use std::env;
-use flate2::read::*;
-//use lzma::LzmaReader;
+use sir_compare_r::input::try_open_input;
 
 
 fn main() {
@@ -21,36 +19,16 @@ fn main() {
         .unwrap()
         .filter_map(Result::ok)
     {
-        let extension = filename.extension()
-            .unwrap()
-            .to_str()
-            .unwrap();
-        let file = match File::open(filename.as_path())
+        let reader = match try_open_input(filename.as_path())
         {
-            Ok(f) => f,
+            Ok(reader) => reader,
             Err(e) => 
             {
                 eprintln!("IO ERROR: {} -> skipping {}", e, filename.display());
                 continue
             }
         };
-        let opt = opt.clone();
-        
-        match extension 
-        {
-            "gz" => {
-                let reader = GzDecoder::new(file);
-                parse_curve(reader, opt, &mut curves);
-            },
-            "xz" => {
-                unimplemented!()
-                //let reader = LzmaReader::new_decompressor(file).unwrap();
-                //parse_curve(reader, opt, &mut curves);
-            },
-            _ => {
-                parse_curve(file, opt, &mut curves);
-            }
-        }
+        parse_curve(reader, opt.clone(), &mut curves);
     }
     let len = curves.len();
     println!("# curves: {}", len);